@@ -0,0 +1,205 @@
+use crate::bounding_box::Bounds3;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use cgmath::InnerSpace;
+use cgmath::Matrix;
+use cgmath::MetricSpace;
+use cgmath::SquareMatrix;
+use cgmath::Transform as CgmathTransform;
+use cgmath::VectorSpace;
+
+/// The maximum number of iterations used when computing the polar
+/// decomposition of a transformation's matrix.
+const POLAR_DECOMPOSITION_MAX_ITERATIONS: usize = 100;
+
+/// A transformation that is interpolated between two keyframe
+/// transformations over a span of time. This allows objects and cameras to
+/// move during the shutter interval, producing motion blur. (p. 103)
+///
+/// Each keyframe matrix is decomposed into translation, rotation, and scale
+/// components. Interpolating the components separately, rather than
+/// interpolating the matrices directly, keeps rigid rotations rigid.
+#[derive(Clone, Copy)]
+pub struct AnimatedTransform {
+    start_transform: transform::Transform,
+    end_transform: transform::Transform,
+    start_time: f32,
+    end_time: f32,
+
+    /// True if the start and end transformations differ.
+    actually_animated: bool,
+
+    /// True if the start and end transformations differ in their rotation.
+    has_rotation: bool,
+
+    translations: [cgmath::Vector3<f32>; 2],
+    rotations: [cgmath::Quaternion<f32>; 2],
+    scales: [cgmath::Matrix4<f32>; 2],
+}
+
+impl AnimatedTransform {
+    /// Creates a transformation that is `start_transform` at `start_time` and
+    /// `end_transform` at `end_time`.
+    pub fn new(
+        start_transform: transform::Transform,
+        start_time: f32,
+        end_transform: transform::Transform,
+        end_time: f32,
+    ) -> Self {
+        let (t0, r0, s0) = decompose(start_transform.matrix());
+        let (t1, mut r1, s1) = decompose(end_transform.matrix());
+
+        // Flip the end rotation if necessary so that interpolation takes the
+        // shortest path.
+        if r0.dot(r1) < 0.0 {
+            r1 = -r1;
+        }
+
+        Self {
+            start_transform,
+            end_transform,
+            start_time,
+            end_time,
+            actually_animated: start_transform != end_transform,
+            has_rotation: r0.dot(r1) < 0.9995,
+            translations: [t0, t1],
+            rotations: [r0, r1],
+            scales: [s0, s1],
+        }
+    }
+
+    /// Returns true if the transformation changes over time.
+    pub fn is_animated(&self) -> bool {
+        self.actually_animated
+    }
+
+    /// Returns the transformation at the given time. Times outside of the
+    /// animated time span are clamped to the span.
+    pub fn interpolate(&self, time: f32) -> transform::Transform {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform;
+        }
+        if time >= self.end_time {
+            return self.end_transform;
+        }
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+
+        let translation = self.translations[0].lerp(self.translations[1], dt);
+        let rotation = self.rotations[0].slerp(self.rotations[1], dt);
+        let scale = self.scales[0] + (self.scales[1] - self.scales[0]) * dt;
+
+        let matrix = cgmath::Matrix4::from_translation(translation)
+            * cgmath::Matrix4::from(rotation)
+            * scale;
+        let inverse = scale.invert().unwrap_or_else(cgmath::Matrix4::identity)
+            * cgmath::Matrix4::from(rotation).transpose()
+            * cgmath::Matrix4::from_translation(-1.0 * translation);
+        transform::Transform::from_matrices(matrix, inverse)
+    }
+
+    /// Returns a bounding box that conservatively encloses the given bounding
+    /// box as it moves over the animated time span.
+    pub fn motion_bounds(&self, bounds: &Bounds3<f32>) -> Bounds3<f32> {
+        if !self.actually_animated {
+            return self.start_transform.transform(bounds);
+        }
+        if !self.has_rotation {
+            // Without rotation, every point moves linearly over time, so the
+            // bounds at the start and end enclose the full motion.
+            return self
+                .start_transform
+                .transform(bounds)
+                .union(&self.end_transform.transform(bounds));
+        }
+
+        // The transformed position of a point p is T(t) + R(t) * S(t) * p.
+        // The translation T(t) moves linearly between its keyframe values, and
+        // since the rotation R(t) preserves length, the remaining term lies
+        // within a sphere whose radius is the largest length of S(t) * p. That
+        // length is convex in t and p, so it is maximized at a keyframe and a
+        // corner of the bounding box.
+        let origin = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let radius = bounds
+            .corners()
+            .into_iter()
+            .flat_map(|p| {
+                self.scales
+                    .iter()
+                    .map(move |s| s.transform_point(p).distance(origin))
+            })
+            .fold(0.0, f32::max);
+        let t0 = self.translations[0];
+        let t1 = self.translations[1];
+        let mut motion_bounds = Bounds3::from_corners(
+            cgmath::Point3::new(t0.x, t0.y, t0.z),
+            cgmath::Point3::new(t1.x, t1.y, t1.z),
+        );
+        motion_bounds.expand(radius);
+        motion_bounds
+    }
+}
+
+impl ApplyTransform<Ray> for AnimatedTransform {
+    /// Transforms the ray by the transformation at the ray's time.
+    fn transform(&self, ray: &Ray) -> Ray {
+        self.interpolate(ray.time).transform(ray)
+    }
+}
+
+/// Decomposes the matrix into translation, rotation, and scale components such
+/// that the matrix equals T * R * S. (p. 104)
+fn decompose(
+    m: &cgmath::Matrix4<f32>,
+) -> (
+    cgmath::Vector3<f32>,
+    cgmath::Quaternion<f32>,
+    cgmath::Matrix4<f32>,
+) {
+    // Extract the translation component.
+    let translation = cgmath::Vector3::new(m[3][0], m[3][1], m[3][2]);
+
+    // Compute the matrix without translation.
+    let mut m = *m;
+    m[3][0] = 0.0;
+    m[3][1] = 0.0;
+    m[3][2] = 0.0;
+    m[0][3] = 0.0;
+    m[1][3] = 0.0;
+    m[2][3] = 0.0;
+    m[3][3] = 1.0;
+
+    // Extract the rotation from the transformation matrix with polar
+    // decomposition, repeatedly averaging the matrix with its inverse
+    // transpose until it converges.
+    let mut r = m;
+    for _ in 0..POLAR_DECOMPOSITION_MAX_ITERATIONS {
+        let r_it = match r.transpose().invert() {
+            Some(r_it) => r_it,
+            None => break,
+        };
+        let r_next = (r + r_it) * 0.5;
+
+        // Compute the norm of the difference between successive iterations.
+        let norm = (0..3)
+            .map(|row| {
+                (0..3)
+                    .map(|col| (r[col][row] - r_next[col][row]).abs())
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max);
+        r = r_next;
+        if norm <= 0.0001 {
+            break;
+        }
+    }
+    let r3 = cgmath::Matrix3::new(
+        r[0][0], r[0][1], r[0][2], r[1][0], r[1][1], r[1][2], r[2][0], r[2][1], r[2][2],
+    );
+    let rotation = cgmath::Quaternion::from(r3).normalize();
+
+    // Compute the scale from the rotation and the original matrix.
+    let scale = r.invert().unwrap_or_else(cgmath::Matrix4::identity) * m;
+
+    (translation, rotation, scale)
+}
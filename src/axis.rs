@@ -8,3 +8,14 @@ pub enum Axis3 {
     Y,
     Z,
 }
+
+impl From<Axis3> for usize {
+    /// Returns the index of the axis's dimension in a 3D point or vector.
+    fn from(axis: Axis3) -> Self {
+        match axis {
+            Axis3::X => 0,
+            Axis3::Y => 1,
+            Axis3::Z => 2,
+        }
+    }
+}
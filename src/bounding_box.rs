@@ -1,8 +1,8 @@
 use crate::axis;
+use crate::math;
 use crate::ray;
 use crate::transform;
 use cgmath::InnerSpace;
-use cgmath::Transform;
 use cgmath::VectorSpace;
 
 pub type Bounds2i = Bounds2<i32>;
@@ -22,6 +22,37 @@ pub struct Bounds3<S> {
     max: cgmath::Point3<S>,
 }
 
+impl<S: cgmath::BaseNum + std::cmp::PartialOrd + std::fmt::Display> Bounds2<S> {
+    /// Creates a bounding box that encloses the given corner points.
+    pub fn from_corners(p1: cgmath::Point2<S>, p2: cgmath::Point2<S>) -> Self {
+        let min = cgmath::Point2::new(min_partial_ord(p1.x, p2.x), min_partial_ord(p1.y, p2.y));
+        let max = cgmath::Point2::new(max_partial_ord(p1.x, p2.x), max_partial_ord(p1.y, p2.y));
+        Self { min, max }
+    }
+
+    /// Returns the minimum corner of the bounding box.
+    pub fn min(&self) -> cgmath::Point2<S> {
+        self.min
+    }
+
+    /// Returns the maximum corner of the bounding box.
+    pub fn max(&self) -> cgmath::Point2<S> {
+        self.max
+    }
+
+    /// Returns a vector across the diagonal of the bounding box, pointing from
+    /// the miminum corner to the maximum corner.
+    pub fn diagonal(&self) -> cgmath::Vector2<S> {
+        self.max - self.min
+    }
+
+    /// Returns true if and only if the point is inside the bounding box
+    /// inclusively.
+    pub fn inside(&self, p: &cgmath::Point2<S>) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+    }
+}
+
 impl<S: cgmath::BaseNum + std::cmp::PartialOrd + std::fmt::Display> Bounds3<S> {
     /// Creates a bounding box that encloses a single point.
     pub fn from_point(p: cgmath::Point3<S>) -> Self {
@@ -43,6 +74,16 @@ impl<S: cgmath::BaseNum + std::cmp::PartialOrd + std::fmt::Display> Bounds3<S> {
         Self { min, max }
     }
 
+    /// Returns the minimum corner of the bounding box.
+    pub fn min(&self) -> cgmath::Point3<S> {
+        self.min
+    }
+
+    /// Returns the maximum corner of the bounding box.
+    pub fn max(&self) -> cgmath::Point3<S> {
+        self.max
+    }
+
     /// Returns the corner points of the bounding box.
     pub fn corners(&self) -> Vec<cgmath::Point3<S>> {
         vec![
@@ -178,7 +219,19 @@ impl<S: cgmath::BaseNum + std::cmp::PartialOrd + std::fmt::Display> Bounds3<S> {
         cgmath::Point3::new(lerp.x, lerp.y, lerp.z)
     }
 
-    // TODO: offset, p. 81
+    /// Returns the position of the point relative to the corners of the
+    /// bounding box. A point at the minimum corner has an offset of (0, 0, 0),
+    /// and a point at the maximum corner has an offset of (1, 1, 1).
+    pub fn offset(&self, p: &cgmath::Point3<S>) -> cgmath::Vector3<S> {
+        let mut o = p - self.min;
+        for dim in 0..3 {
+            if self.max[dim] > self.min[dim] {
+                o[dim] /= self.max[dim] - self.min[dim];
+            }
+        }
+        o
+    }
+
     // TODO: bounding_sphere, p. 81
 }
 
@@ -187,9 +240,6 @@ impl Bounds3<f32> {
     /// through the bounding box. If the ray's origin is inside the bounding box
     /// then the lower bound will be 0.
     pub fn ray_intersection(&self, ray: &ray::Ray) -> Option<(f32, f32)> {
-        // TODO: Consider implementing the optimized version of this function
-        // that takes pre-computed values described on p. 128.
-
         let mut result = (0.0, ray.t_max);
 
         for dim in 0..3 {
@@ -238,16 +288,54 @@ impl Bounds3<f32> {
 
         Some(result)
     }
+
+    /// Returns a boolean indicating whether the ray passes through the
+    /// bounding box within the ray's (0, `ray.t_max`) parametric range.
+    ///
+    /// This is an optimized alternative to `ray_intersection` that takes the
+    /// reciprocal of the ray's direction, `inv_direction`, and flags
+    /// indicating whether each component of the ray's direction is negative,
+    /// `dir_is_neg`, since these can be computed once per ray and reused for
+    /// every bounding box that the ray is tested against. (p. 128)
+    pub fn does_ray_intersect(
+        &self,
+        ray: &ray::Ray,
+        inv_direction: &cgmath::Vector3<f32>,
+        dir_is_neg: &[bool; 3],
+    ) -> bool {
+        // Pad the far intersection so that round-off error can only make the
+        // test more conservative. (p. 221)
+        let far_padding = 1.0 + 2.0 * math::gamma(3);
+
+        let mut t_min = 0.0_f32;
+        let mut t_max = ray.t_max;
+        for dim in 0..3 {
+            let (near, far) = if dir_is_neg[dim] {
+                (self.max[dim], self.min[dim])
+            } else {
+                (self.min[dim], self.max[dim])
+            };
+            let t_near = (near - ray.origin[dim]) * inv_direction[dim];
+            let t_far = (far - ray.origin[dim]) * inv_direction[dim] * far_padding;
+
+            // If the ray's origin lies on a slab plane and the ray is parallel
+            // to it then 0 * inf produces NaN. `f32::max` and `f32::min`
+            // ignore NaN operands, so this dimension doesn't shrink the range.
+            t_min = t_min.max(t_near);
+            t_max = t_max.min(t_far);
+        }
+        t_min <= t_max
+    }
 }
 
-impl transform::Transform<Bounds3<f32>> for cgmath::Matrix4<f32> {
+impl transform::ApplyTransform<Bounds3<f32>> for transform::Transform {
     fn transform(&self, bounds: &Bounds3<f32>) -> Bounds3<f32> {
         // TODO: This could be optimized.
-        let init = Bounds3::from_point(self.transform_point(bounds.min));
+        let init = Bounds3::from_point(self.transform(&bounds.min));
         bounds
             .corners()
             .into_iter()
-            .map(|p| self.transform_point(p))
+            .map(|p| self.transform(&p))
             .fold(init, |b, p| b.union_with_point(&p))
     }
 }
@@ -0,0 +1,568 @@
+use crate::bounding_box::Bounds3;
+use crate::interaction::SurfaceInteraction;
+use crate::primitive::Primitive;
+use crate::ray::Ray;
+
+/// The number of buckets that primitive centroids are binned into when
+/// evaluating the surface area heuristic.
+const SAH_BUCKET_COUNT: usize = 12;
+
+/// The maximum number of primitives that can be stored in a leaf node.
+const MAX_PRIMITIVES_IN_NODE_LIMIT: usize = 255;
+
+/// Describes how primitives are partitioned into two groups when building an
+/// interior node of the hierarchy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Minimize the estimated cost of ray intersection tests using the surface
+    /// area heuristic. (p. 263)
+    Sah,
+
+    /// Partition primitives at the midpoint of their centroids' extent along
+    /// the longest axis.
+    Middle,
+
+    /// Partition primitives into two equally sized groups along the longest
+    /// axis.
+    EqualCounts,
+}
+
+/// A bounding volume hierarchy. Primitives are partitioned into a binary tree
+/// of bounding boxes so that a ray only needs to be tested against the
+/// primitives whose bounding boxes it passes through.
+pub struct Bvh<P> {
+    /// The primitives in the hierarchy, ordered so that the primitives in each
+    /// leaf node are contiguous.
+    primitives: Vec<P>,
+
+    /// The nodes of the hierarchy in depth-first order. The first child of an
+    /// interior node immediately follows it.
+    nodes: Vec<LinearNode>,
+}
+
+/// A node of the hierarchy in its compact, flattened representation.
+struct LinearNode {
+    bounds: Bounds3<f32>,
+    kind: LinearNodeKind,
+}
+
+enum LinearNodeKind {
+    Leaf {
+        /// The index of the node's first primitive in `Bvh::primitives`.
+        primitives_offset: usize,
+        primitive_count: usize,
+    },
+    Interior {
+        /// The index of the node's second child in `Bvh::nodes`.
+        second_child_offset: usize,
+
+        /// The axis along which the node's primitives were partitioned.
+        axis: usize,
+    },
+}
+
+/// A node of the hierarchy as it is represented during construction.
+enum BuildNode {
+    Leaf {
+        bounds: Bounds3<f32>,
+        first_primitive: usize,
+        primitive_count: usize,
+    },
+    Interior {
+        bounds: Bounds3<f32>,
+        axis: usize,
+        children: Box<(BuildNode, BuildNode)>,
+    },
+}
+
+impl BuildNode {
+    fn bounds(&self) -> Bounds3<f32> {
+        match self {
+            BuildNode::Leaf { bounds, .. } => *bounds,
+            BuildNode::Interior { bounds, .. } => *bounds,
+        }
+    }
+}
+
+/// Information about a primitive that is used during construction.
+struct PrimitiveInfo {
+    /// The index of the primitive in the original array of primitives.
+    index: usize,
+    bounds: Bounds3<f32>,
+    centroid: cgmath::Point3<f32>,
+}
+
+#[derive(Clone, Copy)]
+struct SahBucket {
+    count: usize,
+    bounds: Option<Bounds3<f32>>,
+}
+
+impl<P: Primitive> Bvh<P> {
+    /// Builds a bounding volume hierarchy over the given primitives. Leaf nodes
+    /// will contain at most `max_primitives_in_node` primitives, up to a limit
+    /// of 255.
+    pub fn new(
+        primitives: Vec<P>,
+        max_primitives_in_node: usize,
+        split_method: SplitMethod,
+    ) -> Self {
+        if primitives.is_empty() {
+            return Self {
+                primitives,
+                nodes: vec![],
+            };
+        }
+
+        let max_primitives_in_node = max_primitives_in_node.clamp(1, MAX_PRIMITIVES_IN_NODE_LIMIT);
+
+        let mut primitive_info: Vec<PrimitiveInfo> = primitives
+            .iter()
+            .enumerate()
+            .map(|(index, p)| {
+                let bounds = p.world_bound();
+                PrimitiveInfo {
+                    index,
+                    bounds,
+                    centroid: bounds.lerp(0.5),
+                }
+            })
+            .collect();
+
+        let mut node_count = 0;
+        let root = recursive_build(
+            &mut primitive_info,
+            0,
+            max_primitives_in_node,
+            split_method,
+            &mut node_count,
+        );
+
+        // Leaf nodes refer to contiguous ranges of the partitioned primitive
+        // info, so reorder the primitives to match.
+        let mut primitives: Vec<Option<P>> = primitives.into_iter().map(Some).collect();
+        let primitives = primitive_info
+            .iter()
+            .map(|info| primitives[info.index].take().unwrap())
+            .collect();
+
+        let mut nodes = Vec::with_capacity(node_count);
+        flatten(&root, &mut nodes);
+
+        Self { primitives, nodes }
+    }
+
+    /// Returns an axis-aligned bounding box in world space that encloses all
+    /// primitives in the hierarchy.
+    pub fn world_bound(&self) -> Option<Bounds3<f32>> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    /// Returns information about the first ray-primitive intersection, if any,
+    /// in the (0, `ray.t_max`) parametric range along the ray.
+    pub fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)> {
+        let mut ray = *ray;
+        let mut closest = None;
+        self.traverse(&ray.clone(), |primitive| {
+            if let Some((t, interaction)) = primitive.ray_intersection(&ray, test_alpha_texture) {
+                ray.t_max = t;
+                closest = Some((t, interaction));
+            }
+            // Shrink the range of the remaining traversal to the closest hit.
+            (false, ray.t_max)
+        });
+        closest
+    }
+
+    /// Returns a boolean indicating whether the ray intersects any primitive in
+    /// the hierarchy.
+    pub fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool {
+        let mut hit = false;
+        self.traverse(ray, |primitive| {
+            hit = primitive.does_ray_intersect(ray, test_alpha_texture);
+            (hit, ray.t_max)
+        });
+        hit
+    }
+
+    /// Visits every primitive whose leaf node's bounding box is intersected by
+    /// the ray, visiting nodes in front-to-back order along the ray.
+    ///
+    /// `visit` returns a flag indicating whether traversal should stop and the
+    /// upper bound of the ray's parametric range for the rest of the traversal.
+    fn traverse<'b, F>(&'b self, ray: &Ray, mut visit: F)
+    where
+        F: FnMut(&'b P) -> (bool, f32),
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut ray = *ray;
+        let inv_direction = ray.direction.map(|f| 1.0 / f);
+        let dir_is_neg = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+
+        // Nodes that still need to be visited.
+        let mut to_visit = Vec::with_capacity(64);
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node
+                .bounds
+                .does_ray_intersect(&ray, &inv_direction, &dir_is_neg)
+            {
+                match node.kind {
+                    LinearNodeKind::Leaf {
+                        primitives_offset,
+                        primitive_count,
+                    } => {
+                        for primitive in
+                            &self.primitives[primitives_offset..primitives_offset + primitive_count]
+                        {
+                            let (stop, t_max) = visit(primitive);
+                            if stop {
+                                return;
+                            }
+                            ray.t_max = t_max;
+                        }
+                        match to_visit.pop() {
+                            Some(next) => current = next,
+                            None => break,
+                        }
+                    }
+                    LinearNodeKind::Interior {
+                        second_child_offset,
+                        axis,
+                    } => {
+                        // Visit the child nearest to the ray's origin first.
+                        if dir_is_neg[axis] {
+                            to_visit.push(current + 1);
+                            current = second_child_offset;
+                        } else {
+                            to_visit.push(second_child_offset);
+                            current += 1;
+                        }
+                    }
+                }
+            } else {
+                match to_visit.pop() {
+                    Some(next) => current = next,
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+impl<P: Primitive> Primitive for Bvh<P> {
+    /// Returns the bounds of the hierarchy's root node. An empty hierarchy is
+    /// bounded by the single point at the origin.
+    fn world_bound(&self) -> Bounds3<f32> {
+        Bvh::world_bound(self)
+            .unwrap_or_else(|| Bounds3::from_point(cgmath::Point3::new(0.0, 0.0, 0.0)))
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)> {
+        Bvh::ray_intersection(self, ray, test_alpha_texture)
+    }
+
+    fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool {
+        Bvh::does_ray_intersect(self, ray, test_alpha_texture)
+    }
+}
+
+/// Recursively builds the hierarchy over the given slice of primitive info,
+/// partitioning the slice in place. `offset` is the index of the first element
+/// of the slice within the full array of primitive info.
+fn recursive_build(
+    primitive_info: &mut [PrimitiveInfo],
+    offset: usize,
+    max_primitives_in_node: usize,
+    split_method: SplitMethod,
+    node_count: &mut usize,
+) -> BuildNode {
+    *node_count += 1;
+
+    let bounds = primitive_info
+        .iter()
+        .skip(1)
+        .fold(primitive_info[0].bounds, |b, info| b.union(&info.bounds));
+    let primitive_count = primitive_info.len();
+    let leaf = BuildNode::Leaf {
+        bounds,
+        first_primitive: offset,
+        primitive_count,
+    };
+    if primitive_count == 1 {
+        return leaf;
+    }
+
+    // Choose the split dimension based on the extent of the primitive
+    // centroids.
+    let centroid_bounds = primitive_info.iter().skip(1).fold(
+        Bounds3::from_point(primitive_info[0].centroid),
+        |b, info| b.union_with_point(&info.centroid),
+    );
+    let axis = usize::from(centroid_bounds.maximum_extend());
+    if centroid_bounds.max()[axis] == centroid_bounds.min()[axis] {
+        // All centroids are at the same position, so there is no useful way
+        // to partition the primitives.
+        return leaf;
+    }
+
+    let mid = match split_method {
+        SplitMethod::Middle => split_middle(primitive_info, &centroid_bounds, axis)
+            .unwrap_or_else(|| split_equal_counts(primitive_info, axis)),
+        SplitMethod::EqualCounts => split_equal_counts(primitive_info, axis),
+        SplitMethod::Sah => {
+            if primitive_count <= 2 {
+                split_equal_counts(primitive_info, axis)
+            } else {
+                match split_sah(
+                    primitive_info,
+                    &bounds,
+                    &centroid_bounds,
+                    axis,
+                    max_primitives_in_node,
+                ) {
+                    Some(mid) => mid,
+                    None => return leaf,
+                }
+            }
+        }
+    };
+
+    let (first, second) = primitive_info.split_at_mut(mid);
+    let first = recursive_build(
+        first,
+        offset,
+        max_primitives_in_node,
+        split_method,
+        node_count,
+    );
+    let second = recursive_build(
+        second,
+        offset + mid,
+        max_primitives_in_node,
+        split_method,
+        node_count,
+    );
+    BuildNode::Interior {
+        bounds: first.bounds().union(&second.bounds()),
+        axis,
+        children: Box::new((first, second)),
+    }
+}
+
+/// Partitions the primitives about the midpoint of the centroid bounds along
+/// the given axis and returns the index of the first primitive in the second
+/// partition. Returns `None` if all primitives fall in one partition.
+fn split_middle(
+    primitive_info: &mut [PrimitiveInfo],
+    centroid_bounds: &Bounds3<f32>,
+    axis: usize,
+) -> Option<usize> {
+    let midpoint = (centroid_bounds.min()[axis] + centroid_bounds.max()[axis]) / 2.0;
+    let mid = partition(primitive_info, |info| info.centroid[axis] < midpoint);
+    if mid == 0 || mid == primitive_info.len() {
+        None
+    } else {
+        Some(mid)
+    }
+}
+
+/// Partitions the primitives into two equally sized sets such that the
+/// centroids of the first set are all less than the centroids of the second set
+/// along the given axis. Returns the index of the first primitive in the second
+/// partition.
+fn split_equal_counts(primitive_info: &mut [PrimitiveInfo], axis: usize) -> usize {
+    let mid = primitive_info.len() / 2;
+    primitive_info.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[axis]
+            .partial_cmp(&b.centroid[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    mid
+}
+
+/// Partitions the primitives at the bucket boundary with the lowest estimated
+/// cost according to the surface area heuristic. Returns the index of the first
+/// primitive in the second partition, or `None` if it is cheaper to create a
+/// leaf node. (p. 263)
+fn split_sah(
+    primitive_info: &mut [PrimitiveInfo],
+    bounds: &Bounds3<f32>,
+    centroid_bounds: &Bounds3<f32>,
+    axis: usize,
+    max_primitives_in_node: usize,
+) -> Option<usize> {
+    let bucket_index = |info: &PrimitiveInfo| {
+        let b = (SAH_BUCKET_COUNT as f32 * centroid_bounds.offset(&info.centroid)[axis]) as usize;
+        b.min(SAH_BUCKET_COUNT - 1)
+    };
+
+    // Initialize buckets for the SAH partition.
+    let mut buckets = [SahBucket {
+        count: 0,
+        bounds: None,
+    }; SAH_BUCKET_COUNT];
+    for info in primitive_info.iter() {
+        let bucket = &mut buckets[bucket_index(info)];
+        bucket.count += 1;
+        bucket.bounds = Some(match bucket.bounds {
+            Some(b) => b.union(&info.bounds),
+            None => info.bounds,
+        });
+    }
+
+    // Compute costs for splitting after each bucket. Traversal is assumed to
+    // cost 1/8 of a primitive intersection test.
+    let side_cost = |buckets: &[SahBucket]| {
+        let (count, bounds) = buckets.iter().fold((0, None), |(count, bounds), b| {
+            let bounds = match (bounds, b.bounds) {
+                (Some(acc), Some(b)) => Some(Bounds3::union(&acc, &b)),
+                (acc, b) => acc.or(b),
+            };
+            (count + b.count, bounds)
+        });
+        bounds.map_or(0.0, |b| count as f32 * b.surface_area())
+    };
+    let (min_cost_split, min_cost) = (0..SAH_BUCKET_COUNT - 1)
+        .map(|i| {
+            let cost = 0.125
+                + (side_cost(&buckets[..=i]) + side_cost(&buckets[i + 1..]))
+                    / bounds.surface_area();
+            (i, cost)
+        })
+        .fold((0, f32::INFINITY), |(min_i, min_cost), (i, cost)| {
+            if cost < min_cost {
+                (i, cost)
+            } else {
+                (min_i, min_cost)
+            }
+        });
+
+    // Either create a leaf or split the primitives at the selected bucket.
+    let leaf_cost = primitive_info.len() as f32;
+    if primitive_info.len() > max_primitives_in_node || min_cost < leaf_cost {
+        let mid = partition(primitive_info, |info| bucket_index(info) <= min_cost_split);
+        if mid == 0 || mid == primitive_info.len() {
+            Some(split_equal_counts(primitive_info, axis))
+        } else {
+            Some(mid)
+        }
+    } else {
+        None
+    }
+}
+
+/// Reorders the slice so that all elements that satisfy the predicate precede
+/// all elements that don't. Returns the number of elements that satisfy the
+/// predicate.
+fn partition<T, F: Fn(&T) -> bool>(slice: &mut [T], pred: F) -> usize {
+    let mut first_false = 0;
+    for i in 0..slice.len() {
+        if pred(&slice[i]) {
+            slice.swap(i, first_false);
+            first_false += 1;
+        }
+    }
+    first_false
+}
+
+/// Flattens the tree into a depth-first array of nodes and returns the index
+/// of the node that was added for the given root.
+fn flatten(node: &BuildNode, nodes: &mut Vec<LinearNode>) -> usize {
+    let index = nodes.len();
+    match node {
+        BuildNode::Leaf {
+            bounds,
+            first_primitive,
+            primitive_count,
+        } => nodes.push(LinearNode {
+            bounds: *bounds,
+            kind: LinearNodeKind::Leaf {
+                primitives_offset: *first_primitive,
+                primitive_count: *primitive_count,
+            },
+        }),
+        BuildNode::Interior {
+            bounds,
+            axis,
+            children,
+        } => {
+            // Push a placeholder so that the first child immediately follows
+            // this node, then fill in the second child's offset.
+            nodes.push(LinearNode {
+                bounds: *bounds,
+                kind: LinearNodeKind::Interior {
+                    second_child_offset: 0,
+                    axis: *axis,
+                },
+            });
+            flatten(&children.0, nodes);
+            let second_child_offset = flatten(&children.1, nodes);
+            nodes[index].kind = LinearNodeKind::Interior {
+                second_child_offset,
+                axis: *axis,
+            };
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bvh;
+    use super::SplitMethod;
+    use crate::ray::Ray;
+    use crate::shape::sphere::Sphere;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    #[test]
+    fn traverses_hierarchy_deeper_than_64_levels() {
+        // Splitting spheres at exponentially growing distances at the middle
+        // of their centroid bounds splits off one sphere per level, so the
+        // hierarchy is about as deep as the number of spheres.
+        let transforms: Vec<Transform> = (0..120)
+            .map(|i| Transform::translate(Vector3::new(2.0f32.powi(i), 0.0, 0.0)))
+            .collect();
+        let spheres = transforms
+            .iter()
+            .map(|t| Sphere::new(t, false, 0.5, -0.5, 0.5, 360.0))
+            .collect();
+        let bvh = Bvh::new(spheres, 1, SplitMethod::Middle);
+
+        let ray = Ray {
+            origin: Point3::new(-1.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            t_max: f32::INFINITY,
+            ..Default::default()
+        };
+        let (t, _) = bvh.ray_intersection(&ray, false).unwrap();
+        assert!((t - 1.5).abs() < 1e-4);
+        assert!(bvh.does_ray_intersect(&ray, false));
+
+        let ray = Ray {
+            origin: Point3::new(-1.0, 2.0, 0.0),
+            ..ray
+        };
+        assert!(bvh.ray_intersection(&ray, false).is_none());
+        assert!(!bvh.does_ray_intersect(&ray, false));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::bounding_box::Bounds3;
+use crate::interaction::Interaction;
+use crate::interaction::SurfaceInteraction;
+use crate::primitive::Primitive;
+use crate::ray::Ray;
+use crate::shape::Shape;
+use cgmath::InnerSpace;
+
+/// The largest number of times that a ray is followed through a shape's
+/// surface when finding every point where it crosses the surface. This stops
+/// rays that graze a surface from being followed forever.
+const MAX_SURFACE_CROSSINGS: usize = 64;
+
+/// A point where a ray passes through the surface of a solid.
+pub struct Crossing<'a> {
+    /// The parametric t value of the crossing along the ray.
+    pub t: f32,
+
+    /// True if the ray passes into the solid at the crossing, and false if it
+    /// passes out of it.
+    pub entering: bool,
+
+    pub interaction: SurfaceInteraction<'a>,
+}
+
+/// A closed surface that divides space into an inside and an outside, which
+/// can be combined with other solids by a `Csg` node.
+///
+/// Every shape is a solid, as long as its surface is closed and its normals
+/// point to the outside. The inside of a shape whose normals point inwards,
+/// such as one with a reversed orientation, is the space outside its surface.
+pub trait Solid {
+    /// Returns an axis-aligned bounding box in world space.
+    fn world_bound(&self) -> Bounds3<f32>;
+
+    /// Returns every point, in order, where the ray passes through the
+    /// solid's surface in the (0, infinity) parametric range along the ray.
+    /// The ray's `t_max` is ignored, since points beyond it determine whether
+    /// the ray is inside the solid before it.
+    fn crossings(&self, ray: &Ray, test_alpha_texture: bool) -> Vec<Crossing>;
+}
+
+impl<S: Shape> Solid for S {
+    fn world_bound(&self) -> Bounds3<f32> {
+        Shape::world_bound(self)
+    }
+
+    fn crossings(&self, ray: &Ray, test_alpha_texture: bool) -> Vec<Crossing> {
+        let direction_length2 = ray.direction.magnitude2();
+        if direction_length2 == 0.0 {
+            return vec![];
+        }
+
+        // Follow the ray through the surface, spawning a new ray from each
+        // crossing to find the next one.
+        let mut crossings: Vec<Crossing> = vec![];
+        let mut next_ray = Ray {
+            t_max: f32::INFINITY,
+            ..*ray
+        };
+        while crossings.len() < MAX_SURFACE_CROSSINGS {
+            let interaction = match Shape::ray_intersection(self, &next_ray, test_alpha_texture) {
+                Some((_, interaction)) => interaction,
+                None => break,
+            };
+
+            // Every ray after the first starts at the previous crossing, so
+            // measure t along the original ray instead.
+            let t = (interaction.point() - ray.origin).dot(ray.direction) / direction_length2;
+            let entering = interaction
+                .normal()
+                .is_some_and(|n| n.dot(ray.direction) < 0.0);
+            next_ray = interaction.spawn_ray(ray.direction);
+            crossings.push(Crossing {
+                t,
+                entering,
+                interaction,
+            });
+        }
+        crossings
+    }
+}
+
+/// The boolean operation that a `Csg` node combines its solids with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOperation {
+    /// The space inside either solid.
+    Union,
+
+    /// The space inside both solids.
+    Intersection,
+
+    /// The space inside the first solid but not the second.
+    Difference,
+}
+
+impl CsgOperation {
+    /// Returns a flag that indicates whether a point is inside the combined
+    /// solid, given whether it is inside each of the two solids.
+    fn contains(self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_a || inside_b,
+            CsgOperation::Intersection => inside_a && inside_b,
+            CsgOperation::Difference => inside_a && !inside_b,
+        }
+    }
+}
+
+/// A node of a constructive solid geometry tree, which combines two solids
+/// with a boolean operation. Nodes are solids themselves, so they can be
+/// combined into larger trees.
+///
+/// A ray is intersected with the node by finding every point where it crosses
+/// each solid's surface, and walking through the crossings in order while
+/// tracking whether the ray is inside each solid. The crossings where the ray
+/// passes into or out of the combined solid are the node's crossings. The
+/// normals on the surface of a subtracted solid are flipped, so that they
+/// point to the outside of the combined solid.
+pub struct Csg<'a> {
+    operation: CsgOperation,
+    a: &'a dyn Solid,
+    b: &'a dyn Solid,
+
+    /// The bounds of the combined solid.
+    bounds: Bounds3<f32>,
+}
+
+impl<'a> Csg<'a> {
+    /// Creates a node that combines the solids `a` and `b` with `operation`.
+    /// Returns `None` if the solids' bounds show that the combined solid is
+    /// empty, since an empty solid has no meaningful bounds.
+    pub fn new(operation: CsgOperation, a: &'a dyn Solid, b: &'a dyn Solid) -> Option<Self> {
+        let bounds_a = a.world_bound();
+        let bounds_b = b.world_bound();
+        let bounds = match operation {
+            CsgOperation::Union => bounds_a.union(&bounds_b),
+            CsgOperation::Intersection => bounds_a.intersection(&bounds_b)?,
+            CsgOperation::Difference => bounds_a,
+        };
+        Some(Self {
+            operation,
+            a,
+            b,
+            bounds,
+        })
+    }
+
+    /// Returns the crossings of the solid's surface along the ray, or no
+    /// crossings if the ray misses the solid's bounds.
+    fn operand_crossings(
+        solid: &'a dyn Solid,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Vec<Crossing<'a>> {
+        if solid.world_bound().ray_intersection(ray).is_some() {
+            solid.crossings(ray, test_alpha_texture)
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl<'a> Solid for Csg<'a> {
+    fn world_bound(&self) -> Bounds3<f32> {
+        self.bounds
+    }
+
+    fn crossings(&self, ray: &Ray, test_alpha_texture: bool) -> Vec<Crossing> {
+        let ray = Ray {
+            t_max: f32::INFINITY,
+            ..*ray
+        };
+        if self.bounds.ray_intersection(&ray).is_none() {
+            return vec![];
+        }
+
+        // The second solid can't affect an intersection or difference when
+        // the ray doesn't pass through the first solid or the solids don't
+        // overlap.
+        let crossings_a = Csg::operand_crossings(self.a, &ray, test_alpha_texture);
+        let b_is_needed = match self.operation {
+            CsgOperation::Union => true,
+            CsgOperation::Intersection | CsgOperation::Difference => {
+                !crossings_a.is_empty() && self.a.world_bound().overlaps(&self.b.world_bound())
+            }
+        };
+        let crossings_b = if b_is_needed {
+            Csg::operand_crossings(self.b, &ray, test_alpha_texture)
+        } else {
+            vec![]
+        };
+
+        // The ray starts inside a solid if its first crossing leaves it.
+        let mut inside_a = crossings_a.first().is_some_and(|c| !c.entering);
+        let mut inside_b = crossings_b.first().is_some_and(|c| !c.entering);
+        let mut inside = self.operation.contains(inside_a, inside_b);
+
+        // Merge the crossings in order along the ray and keep the ones where
+        // the ray passes into or out of the combined solid.
+        let mut result = vec![];
+        let mut crossings_a = crossings_a.into_iter().peekable();
+        let mut crossings_b = crossings_b.into_iter().peekable();
+        loop {
+            let from_b = match (crossings_a.peek(), crossings_b.peek()) {
+                (Some(a), Some(b)) => b.t < a.t,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (None, None) => break,
+            };
+            let mut crossing = if from_b {
+                crossings_b.next()
+            } else {
+                crossings_a.next()
+            }
+            .unwrap();
+
+            if from_b {
+                inside_b = crossing.entering;
+            } else {
+                inside_a = crossing.entering;
+            }
+            let now_inside = self.operation.contains(inside_a, inside_b);
+            if now_inside != inside {
+                inside = now_inside;
+                if from_b && self.operation == CsgOperation::Difference {
+                    crossing.interaction.reverse_normals();
+                }
+                crossing.entering = now_inside;
+                result.push(crossing);
+            }
+        }
+        result
+    }
+}
+
+impl<'a> Primitive for Csg<'a> {
+    fn world_bound(&self) -> Bounds3<f32> {
+        Solid::world_bound(self)
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)> {
+        self.crossings(ray, test_alpha_texture)
+            .into_iter()
+            .find(|c| c.t > 0.0 && c.t < ray.t_max)
+            .map(|c| (c.t, c.interaction))
+    }
+
+    fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool {
+        Primitive::ray_intersection(self, ray, test_alpha_texture).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Csg;
+    use super::CsgOperation;
+    use super::Solid;
+    use crate::shape::sphere::Sphere;
+    use crate::transform::Transform;
+    use cgmath::Vector3;
+
+    #[test]
+    fn new_rejects_intersection_of_disjoint_solids() {
+        let left = Transform::translate(Vector3::new(-2.0, 0.0, 0.0));
+        let right = Transform::translate(Vector3::new(2.0, 0.0, 0.0));
+        let a = Sphere::new(&left, false, 1.0, -1.0, 1.0, 360.0);
+        let b = Sphere::new(&right, false, 1.0, -1.0, 1.0, 360.0);
+        assert!(Csg::new(CsgOperation::Intersection, &a, &b).is_none());
+        let union = Csg::new(CsgOperation::Union, &a, &b).unwrap();
+        let bounds = union.world_bound();
+        assert!(bounds.min().x <= -3.0 && bounds.max().x >= 3.0);
+    }
+
+    #[test]
+    fn new_bounds_intersection_of_overlapping_solids() {
+        let left = Transform::translate(Vector3::new(-0.5, 0.0, 0.0));
+        let right = Transform::translate(Vector3::new(0.5, 0.0, 0.0));
+        let a = Sphere::new(&left, false, 1.0, -1.0, 1.0, 360.0);
+        let b = Sphere::new(&right, false, 1.0, -1.0, 1.0, 360.0);
+        let intersection = Csg::new(CsgOperation::Intersection, &a, &b).unwrap();
+        let bounds = intersection.world_bound();
+        assert!(bounds.min().x >= -0.5 - 1e-3 && bounds.max().x <= 0.5 + 1e-3);
+    }
+}
@@ -1,10 +1,15 @@
+use crate::light;
+use crate::material;
+use crate::math;
 use crate::medium;
+use crate::ray;
 use crate::shape;
 use crate::transform;
+use crate::transform::ApplyTransformWithError;
 use crate::vector;
 use crate::vector::face_forward;
+use crate::vector::Normal3;
 use cgmath::InnerSpace;
-use cgmath::Transform;
 
 /// Represents the geometry at a particular point on a surface. This point is
 /// often found by intersecting a ray against the surface.
@@ -25,21 +30,27 @@ pub struct SurfaceInteraction<'a> {
     /// error is (0, 0, 0) for points in participating media.
     point_error_bound: cgmath::Vector3<f32>,
 
-    /// If the nteraction occurs on a surface, this is the surface normal at the
-    /// interaction point.
-    neg_ray_direction: Option<cgmath::Vector3<f32>>,
-
     /// The direction of the negative/outgoing ray if the interaction lies along
     /// a ray. Interactions that do not exist on a ray, such as those found by
     /// randomly sampling points on a surface, won't have a negative ray
     /// direction.
-    time: std::time::Instant,
+    neg_ray_direction: Option<cgmath::Vector3<f32>>,
+
+    /// The scene time at which the interaction occurs. For interactions found
+    /// by ray intersection this is the time of the ray.
+    time: f32,
 
     /// The scattering media at the interaction point.
     medium_interface: Option<medium::MediumInterface>,
 
     /// The shape that the point lies on.
-    shape: &'a shape::GenericShape,
+    shape: &'a dyn shape::Shape,
+
+    /// The area light that the shape belongs to, if the shape is emissive.
+    area_light: Option<&'a dyn light::AreaLight>,
+
+    /// The material of the surface at the interaction point.
+    material: Option<&'a dyn material::Material>,
 
     /// The surface normal at the interaction point.
     normal: cgmath::Vector3<f32>,
@@ -82,9 +93,9 @@ impl<'a> SurfaceInteraction<'a> {
         point: cgmath::Point3<f32>,
         point_error_bound: cgmath::Vector3<f32>,
         neg_ray_direction: Option<cgmath::Vector3<f32>>,
-        time: std::time::Instant,
+        time: f32,
 
-        shape: &'a shape::GenericShape,
+        shape: &'a dyn shape::Shape,
         uv: cgmath::Point2<f32>,
         dpdu: cgmath::Vector3<f32>,
         dpdv: cgmath::Vector3<f32>,
@@ -93,12 +104,10 @@ impl<'a> SurfaceInteraction<'a> {
     ) -> Self {
         // Flip the normal if EITHER normals are reveresed or the shape's
         // transform swaps handedness. (See p. 118 for detailed explanation.)
-        let normal = if (shape.reverse_orientation && !shape.transform_swaps_handedness)
-            || (!shape.reverse_orientation && shape.transform_swaps_handedness)
-        {
-            dpdu.cross(dpdv).normalize()
-        } else {
+        let normal = if shape.reverse_orientation() ^ shape.object_to_world_swaps_handedness() {
             dpdu.cross(dpdv).map(|f| -1.0 * f).normalize()
+        } else {
+            dpdu.cross(dpdv).normalize()
         };
 
         Self {
@@ -110,6 +119,8 @@ impl<'a> SurfaceInteraction<'a> {
             medium_interface: None,
             // Surface interaction fields.
             shape,
+            area_light: None,
+            material: None,
             normal,
             uv,
             dpdu,
@@ -126,6 +137,52 @@ impl<'a> SurfaceInteraction<'a> {
         }
     }
 
+    /// Returns the shape that the interaction point lies on.
+    pub fn shape(&self) -> &'a dyn shape::Shape {
+        self.shape
+    }
+
+    /// Returns the area light that the shape belongs to, if any.
+    pub fn area_light(&self) -> Option<&'a dyn light::AreaLight> {
+        self.area_light
+    }
+
+    /// Returns the material of the surface at the interaction point, if any.
+    pub fn material(&self) -> Option<&'a dyn material::Material> {
+        self.material
+    }
+
+    /// Returns the UV coordinates of the point on the surface.
+    pub fn uv(&self) -> cgmath::Point2<f32> {
+        self.uv
+    }
+
+    /// Sets the area light that the shape belongs to.
+    pub fn set_area_light(&mut self, area_light: Option<&'a dyn light::AreaLight>) {
+        self.area_light = area_light;
+    }
+
+    /// Sets the material of the surface at the interaction point.
+    pub fn set_material(&mut self, material: Option<&'a dyn material::Material>) {
+        self.material = material;
+    }
+
+    /// Overrides the geometric surface normal and the shading geometry normal.
+    /// This is useful for shapes, such as triangles, whose true geometric
+    /// normal is not necessarily given by the cross product of `dpdu` and
+    /// `dpdv`.
+    pub fn set_normal(&mut self, normal: cgmath::Vector3<f32>) {
+        self.normal = normal;
+        self.shading_geometry.normal = normal;
+    }
+
+    /// Flips the geometric surface normal and the shading geometry normal so
+    /// that they point to the other side of the surface.
+    pub fn reverse_normals(&mut self) {
+        self.normal = -1.0 * self.normal;
+        self.shading_geometry.normal = -1.0 * self.shading_geometry.normal;
+    }
+
     pub fn set_shading_geometry(
         &mut self,
         dpdu: cgmath::Vector3<f32>,
@@ -158,32 +215,37 @@ impl<'a> SurfaceInteraction<'a> {
     }
 }
 
-impl<'a> transform::Transform<SurfaceInteraction<'a>> for cgmath::Matrix4<f32> {
+impl<'a> transform::ApplyTransform<SurfaceInteraction<'a>> for transform::Transform {
     fn transform(&self, interaction: &SurfaceInteraction<'a>) -> SurfaceInteraction<'a> {
+        let transform_normal = |n: cgmath::Vector3<f32>| self.transform(&Normal3(n)).0;
+
+        let (point, point_error_bound) =
+            self.transform_with_error_bound(&interaction.point, &interaction.point_error_bound);
+
         let mut transformed = SurfaceInteraction {
-            point: interaction.point, // FIXME: Handle point transformation in Section 3.9.
-            point_error_bound: interaction.point_error_bound, // FIXME: Handle error transformation in Section 3.9.
+            point,
+            point_error_bound,
             neg_ray_direction: interaction
                 .neg_ray_direction
-                .map(|d| self.transform_vector(d).normalize()),
+                .map(|d| self.transform(&d).normalize()),
             time: interaction.time,
             medium_interface: interaction.medium_interface,
             // Surface interaction fields.
             shape: interaction.shape,
-            normal: self.transform_vector(interaction.normal).normalize(),
+            area_light: interaction.area_light,
+            material: interaction.material,
+            normal: transform_normal(interaction.normal).normalize(),
             uv: interaction.uv,
-            dpdu: self.transform_vector(interaction.dpdu),
-            dpdv: self.transform_vector(interaction.dpdv),
-            dndu: self.transform_vector(interaction.dndu),
-            dndv: self.transform_vector(interaction.dndv),
+            dpdu: self.transform(&interaction.dpdu),
+            dpdv: self.transform(&interaction.dpdv),
+            dndu: transform_normal(interaction.dndu),
+            dndv: transform_normal(interaction.dndv),
             shading_geometry: ShadingGeometry {
-                normal: self
-                    .transform_vector(interaction.shading_geometry.normal)
-                    .normalize(),
-                dpdu: self.transform_vector(interaction.shading_geometry.dpdu),
-                dpdv: self.transform_vector(interaction.shading_geometry.dpdv),
-                dndu: self.transform_vector(interaction.shading_geometry.dndu),
-                dndv: self.transform_vector(interaction.shading_geometry.dndv),
+                normal: transform_normal(interaction.shading_geometry.normal).normalize(),
+                dpdu: self.transform(&interaction.shading_geometry.dpdu),
+                dpdv: self.transform(&interaction.shading_geometry.dpdv),
+                dndu: transform_normal(interaction.shading_geometry.dndu),
+                dndv: transform_normal(interaction.shading_geometry.dndv),
             },
         };
         transformed.shading_geometry.normal =
@@ -192,7 +254,7 @@ impl<'a> transform::Transform<SurfaceInteraction<'a>> for cgmath::Matrix4<f32> {
     }
 }
 
-trait Interaction {
+pub trait Interaction {
     /// Returns the point in space where the interaction occurs.
     fn point(&self) -> cgmath::Point3<f32>;
 
@@ -212,11 +274,127 @@ trait Interaction {
     /// ray direction.
     fn neg_ray_direction(&self) -> Option<cgmath::Vector3<f32>>;
 
-    /// Returns the instance in time when the interaction occurs.
-    fn time(&self) -> std::time::Instant;
+    /// Returns the scene time at which the interaction occurs.
+    fn time(&self) -> f32;
 
     /// Returns the scattering media at the interaction point.
     fn medium_interface(&self) -> &Option<medium::MediumInterface>;
+
+    /// Returns the medium that a ray leaving the interaction point in the
+    /// direction `w` will travel through.
+    fn medium(&self, w: cgmath::Vector3<f32>) -> Option<medium::Medium> {
+        self.medium_interface().and_then(|mi| match self.normal() {
+            Some(n) if w.dot(n) <= 0.0 => mi.inside,
+            _ => mi.outside,
+        })
+    }
+
+    /// Returns a ray leaving the interaction point in the given direction.
+    ///
+    /// The ray's origin is offset from the interaction point far enough that
+    /// the ray won't incorrectly re-intersect the surface the point lies on.
+    fn spawn_ray(&self, direction: cgmath::Vector3<f32>) -> ray::Ray {
+        let origin = offset_ray_origin(
+            self.point(),
+            self.point_error_bound(),
+            self.normal(),
+            direction,
+        );
+        ray::Ray {
+            origin,
+            direction,
+            t_max: f32::INFINITY,
+            time: self.time(),
+            medium: self.medium(direction),
+        }
+    }
+
+    /// Returns a ray leaving the interaction point that ends just before the
+    /// given point. The ray's direction is not normalized, so the target point
+    /// is at a parametric value of 1.
+    fn spawn_ray_to(&self, point: cgmath::Point3<f32>) -> ray::Ray {
+        let origin = offset_ray_origin(
+            self.point(),
+            self.point_error_bound(),
+            self.normal(),
+            point - self.point(),
+        );
+        let direction = point - origin;
+        ray::Ray {
+            origin,
+            direction,
+            t_max: 1.0 - SHADOW_EPSILON,
+            time: self.time(),
+            medium: self.medium(direction),
+        }
+    }
+
+    /// Returns a ray leaving the interaction point that ends just before the
+    /// point of another interaction. Both the origin and the endpoint of the
+    /// ray are offset from their respective surfaces.
+    fn spawn_ray_to_interaction(&self, other: &dyn Interaction) -> ray::Ray {
+        let origin = offset_ray_origin(
+            self.point(),
+            self.point_error_bound(),
+            self.normal(),
+            other.point() - self.point(),
+        );
+        let target = offset_ray_origin(
+            other.point(),
+            other.point_error_bound(),
+            other.normal(),
+            origin - other.point(),
+        );
+        let direction = target - origin;
+        ray::Ray {
+            origin,
+            direction,
+            t_max: 1.0 - SHADOW_EPSILON,
+            time: self.time(),
+            medium: self.medium(direction),
+        }
+    }
+}
+
+/// The fraction of a ray's length that is cut off the end of rays spawned
+/// towards a target point so that the ray does not intersect the surface the
+/// target lies on.
+const SHADOW_EPSILON: f32 = 0.0001;
+
+/// Returns a ray origin that is offset from `point` along the surface normal
+/// `normal` by enough that the origin is guaranteed to lie on the same side of
+/// the surface as the direction `w`. (p. 231)
+fn offset_ray_origin(
+    point: cgmath::Point3<f32>,
+    point_error_bound: cgmath::Vector3<f32>,
+    normal: Option<cgmath::Vector3<f32>>,
+    w: cgmath::Vector3<f32>,
+) -> cgmath::Point3<f32> {
+    let normal = match normal {
+        Some(n) => n,
+        None => return point,
+    };
+
+    // Offset along the normal by the projection of the error bound onto the
+    // normal, in the hemisphere that the ray leaves towards.
+    let d = normal.map(|f| f.abs()).dot(point_error_bound);
+    let offset = d * normal;
+    let offset = if w.dot(normal) < 0.0 {
+        -1.0 * offset
+    } else {
+        offset
+    };
+    let mut origin = point + offset;
+
+    // Round the offset point away from the surface.
+    for dim in 0..3 {
+        if offset[dim] > 0.0 {
+            origin[dim] = math::next_float_up(origin[dim]);
+        } else if offset[dim] < 0.0 {
+            origin[dim] = math::next_float_down(origin[dim]);
+        }
+    }
+    origin
 }
 
 impl<'a> Interaction for SurfaceInteraction<'a> {
@@ -236,7 +414,86 @@ impl<'a> Interaction for SurfaceInteraction<'a> {
         self.neg_ray_direction
     }
 
-    fn time(&self) -> std::time::Instant {
+    fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn medium_interface(&self) -> &Option<medium::MediumInterface> {
+        &self.medium_interface
+    }
+}
+
+/// A point on a surface that was not found by ray intersection, such as a
+/// point sampled on a shape's surface. Unlike `SurfaceInteraction`, it carries
+/// only the geometry needed to spawn rays to and from the point.
+#[derive(Clone, Copy)]
+pub struct SurfacePoint {
+    /// The point in space where the interaction occurs.
+    point: cgmath::Point3<f32>,
+
+    /// A conservative bound on the error in `point`.
+    point_error_bound: cgmath::Vector3<f32>,
+
+    /// The surface normal at the point.
+    normal: cgmath::Vector3<f32>,
+
+    /// The UV coordinates of the point on the surface.
+    uv: cgmath::Point2<f32>,
+
+    /// The scene time at which the interaction occurs.
+    time: f32,
+
+    /// The scattering media at the point.
+    medium_interface: Option<medium::MediumInterface>,
+}
+
+impl SurfacePoint {
+    pub fn new(
+        point: cgmath::Point3<f32>,
+        point_error_bound: cgmath::Vector3<f32>,
+        normal: cgmath::Vector3<f32>,
+        uv: cgmath::Point2<f32>,
+    ) -> Self {
+        Self {
+            point,
+            point_error_bound,
+            normal,
+            uv,
+            time: 0.0,
+            medium_interface: None,
+        }
+    }
+
+    /// Returns the UV coordinates of the point on the surface.
+    pub fn uv(&self) -> cgmath::Point2<f32> {
+        self.uv
+    }
+
+    /// Sets the scene time at which the interaction occurs. Sampled points
+    /// usually take the time of the interaction they were sampled from.
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+}
+
+impl Interaction for SurfacePoint {
+    fn point(&self) -> cgmath::Point3<f32> {
+        self.point
+    }
+
+    fn point_error_bound(&self) -> cgmath::Vector3<f32> {
+        self.point_error_bound
+    }
+
+    fn normal(&self) -> Option<cgmath::Vector3<f32>> {
+        Some(self.normal)
+    }
+
+    fn neg_ray_direction(&self) -> Option<cgmath::Vector3<f32>> {
+        None
+    }
+
+    fn time(&self) -> f32 {
         self.time
     }
 
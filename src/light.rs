@@ -0,0 +1,2 @@
+/// A light source that emits light from the surface of a shape.
+pub trait AreaLight {}
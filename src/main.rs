@@ -1,10 +1,18 @@
+mod animated_transform;
 mod axis;
 mod bounding_box;
+mod bvh;
+mod csg;
 mod interaction;
+mod light;
+mod material;
 mod math;
 mod medium;
+mod primitive;
 mod ray;
+mod sampling;
 mod shape;
+mod texture;
 mod transform;
 mod vector;
 
@@ -0,0 +1,2 @@
+/// Describes how light scatters at a point on a surface.
+pub trait Material {}
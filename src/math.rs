@@ -1,3 +1,4 @@
+use cgmath::Point3;
 use cgmath::Vector3;
 use efloat::EFloat32;
 use std::ops::Mul;
@@ -14,3 +15,210 @@ use std::ops::Mul;
 //         cgmath::Vector3::new(self * rhs.x, self * rhs.y, self * rhs.z)
 //     }
 // }
+
+/// Half of the difference between 1 and the next largest representable `f32`.
+/// This bounds the relative error introduced by a single correctly rounded
+/// floating-point operation. (p. 208)
+pub const MACHINE_EPSILON: f32 = f32::EPSILON * 0.5;
+
+/// Returns a conservative bound on the relative error accumulated by `n`
+/// successive floating-point operations. (p. 216)
+pub fn gamma(n: i32) -> f32 {
+    let n = n as f32;
+    (n * MACHINE_EPSILON) / (1.0 - n * MACHINE_EPSILON)
+}
+
+/// Clamps `x` to the inclusive range [`low`, `high`].
+pub fn clamp(x: f32, low: f32, high: f32) -> f32 {
+    if x < low {
+        low
+    } else if x > high {
+        high
+    } else {
+        x
+    }
+}
+
+/// Linearly interpolates between `a` and `b`.
+pub fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    (1.0 - t) * a + t * b
+}
+
+/// Linearly interpolates between the points `a` and `b`.
+pub fn lerp_point(t: f32, a: Point3<f32>, b: Point3<f32>) -> Point3<f32> {
+    a + (b - a) * t
+}
+
+/// Solves the quadratic equation a*t^2 + b*t + c = 0 and returns the two
+/// roots, with the smaller root first. Returns `None` if there are no real
+/// roots.
+///
+/// The discriminant is computed in double precision and the error of each
+/// coefficient is carried through into the returned roots, so the true roots
+/// are guaranteed to lie within the returned roots' error bounds. (p. 1079)
+pub fn quadratic(a: EFloat32, b: EFloat32, c: EFloat32) -> Option<(EFloat32, EFloat32)> {
+    let a_f64 = f32::from(a) as f64;
+    let b_f64 = f32::from(b) as f64;
+    let c_f64 = f32::from(c) as f64;
+
+    let discriminant = b_f64 * b_f64 - 4.0 * a_f64 * c_f64;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root_discriminant = discriminant.sqrt() as f32;
+    let root_discriminant =
+        EFloat32::new_with_err(root_discriminant, MACHINE_EPSILON * root_discriminant);
+
+    // Avoid catastrophic cancellation when b and the square root of the
+    // discriminant are nearly equal. (p. 1080)
+    let q = if f32::from(b) < 0.0 {
+        EFloat32::new(-0.5) * (b - root_discriminant)
+    } else {
+        EFloat32::new(-0.5) * (b + root_discriminant)
+    };
+    let t0 = q / a;
+    let t1 = c / q;
+
+    if f32::from(t0) > f32::from(t1) {
+        Some((t1, t0))
+    } else {
+        Some((t0, t1))
+    }
+}
+
+/// Returns the next representable `f32` that is greater than `v`. (p. 212)
+pub fn next_float_up(v: f32) -> f32 {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    // Skip negative zero so that the result is the smallest positive value.
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    let bits = if v >= 0.0 { bits + 1 } else { bits - 1 };
+    f32::from_bits(bits)
+}
+
+/// Returns the next representable `f32` that is less than `v`.
+pub fn next_float_down(v: f32) -> f32 {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    let bits = if v > 0.0 { bits - 1 } else { bits + 1 };
+    f32::from_bits(bits)
+}
+
+/// The maximum number of iterations used to refine each root in
+/// `polynomial_roots`.
+const MAX_ROOT_REFINEMENT_ITERATIONS: usize = 100;
+
+/// Returns the real roots of the polynomial
+/// c[0] + c[1]*t + c[2]*t^2 + ... that lie in the closed range
+/// [`t_min`, `t_max`], in increasing order. Both ends of the range must be
+/// finite.
+///
+/// Rather than evaluating a closed-form solution, which suffers from
+/// catastrophic cancellation for nearly-repeated roots, the roots of the
+/// polynomial's derivative are found recursively. They split the range into
+/// intervals on which the polynomial is monotonic, so each interval contains
+/// at most one root, and that root is refined with safeguarded Newton
+/// iteration. Nearly-tangent roots therefore stay bracketed no matter how
+/// close together they are.
+pub fn polynomial_roots(coefficients: &[f64], t_min: f64, t_max: f64) -> Vec<f64> {
+    // Drop vanishing high-order coefficients so the leading coefficient is
+    // non-zero.
+    let degree = match coefficients.iter().rposition(|&c| c != 0.0) {
+        Some(degree) => degree,
+        None => return vec![],
+    };
+    let coefficients = &coefficients[..=degree];
+
+    match degree {
+        0 => vec![],
+        1 => {
+            let t = -1.0 * coefficients[0] / coefficients[1];
+            if t >= t_min && t <= t_max {
+                vec![t]
+            } else {
+                vec![]
+            }
+        }
+        _ => {
+            let derivative: Vec<f64> = coefficients[1..]
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (i + 1) as f64 * c)
+                .collect();
+            let mut bounds = vec![t_min];
+            bounds.extend(polynomial_roots(&derivative, t_min, t_max));
+            bounds.push(t_max);
+
+            let mut roots = vec![];
+            for interval in bounds.windows(2) {
+                let (lo, hi) = (interval[0], interval[1]);
+                let f_lo = evaluate_polynomial(coefficients, lo);
+                let f_hi = evaluate_polynomial(coefficients, hi);
+                if f_lo == 0.0 {
+                    if roots.last() != Some(&lo) {
+                        roots.push(lo);
+                    }
+                } else if f_hi != 0.0 && f_lo.is_sign_negative() != f_hi.is_sign_negative() {
+                    roots.push(refine_root(coefficients, lo, hi, f_lo));
+                }
+            }
+            if evaluate_polynomial(coefficients, t_max) == 0.0 && roots.last() != Some(&t_max) {
+                roots.push(t_max);
+            }
+            roots
+        }
+    }
+}
+
+/// Evaluates the polynomial c[0] + c[1]*t + c[2]*t^2 + ... at `t` with
+/// Horner's method.
+fn evaluate_polynomial(coefficients: &[f64], t: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |acc, &c| acc * t + c)
+}
+
+/// Evaluates the polynomial c[0] + c[1]*t + c[2]*t^2 + ... and its derivative
+/// at `t` with Horner's method.
+fn evaluate_polynomial_and_derivative(coefficients: &[f64], t: f64) -> (f64, f64) {
+    coefficients
+        .iter()
+        .rev()
+        .fold((0.0, 0.0), |(f, df), &c| (f * t + c, df * t + f))
+}
+
+/// Finds the single root of the polynomial in the range (`lo`, `hi`), given
+/// that the polynomial has the value `f_lo` at `lo` and changes sign over the
+/// range. Newton steps are taken when they stay inside the bracketing range;
+/// otherwise the range is bisected.
+fn refine_root(coefficients: &[f64], mut lo: f64, mut hi: f64, f_lo: f64) -> f64 {
+    let mut t = 0.5 * (lo + hi);
+    for _ in 0..MAX_ROOT_REFINEMENT_ITERATIONS {
+        let (f, df) = evaluate_polynomial_and_derivative(coefficients, t);
+        if f == 0.0 {
+            return t;
+        }
+
+        // Shrink the bracket to the half that still contains the sign change.
+        if f.is_sign_negative() == f_lo.is_sign_negative() {
+            lo = t;
+        } else {
+            hi = t;
+        }
+
+        let newton_t = t - f / df;
+        let next_t = if newton_t > lo && newton_t < hi {
+            newton_t
+        } else {
+            0.5 * (lo + hi)
+        };
+        if next_t == t || hi - lo <= f64::EPSILON * t.abs() {
+            return next_t;
+        }
+        t = next_t;
+    }
+    t
+}
@@ -1,5 +1,13 @@
 #[derive(Clone, Copy)]
 pub struct Medium {}
 
+/// Describes the scattering media on either side of a surface.
 #[derive(Clone, Copy)]
-pub struct MediumInterface {}
+pub struct MediumInterface {
+    /// The medium on the side of the surface opposite the surface normal.
+    pub inside: Option<Medium>,
+
+    /// The medium on the side of the surface that the surface normal points
+    /// towards.
+    pub outside: Option<Medium>,
+}
@@ -0,0 +1,107 @@
+use crate::animated_transform::AnimatedTransform;
+use crate::bounding_box::Bounds3;
+use crate::interaction::SurfaceInteraction;
+use crate::ray::Ray;
+use crate::shape::Shape;
+use crate::transform;
+use crate::transform::ApplyTransform;
+
+/// Something that rays can be intersected with in world space, such as a
+/// shape, an aggregate of primitives, or an instance of another primitive.
+/// (p. 248)
+pub trait Primitive {
+    /// Returns an axis-aligned bounding box in world space.
+    fn world_bound(&self) -> Bounds3<f32>;
+
+    /// Returns information about the first ray-primitive intersection, if any,
+    /// in the (0, `ray.t_max`) parametric range along the ray.
+    fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)>;
+
+    /// Returns a boolean indicating whether the ray intersects the primitive.
+    fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool;
+}
+
+impl<S: Shape> Primitive for S {
+    fn world_bound(&self) -> Bounds3<f32> {
+        Shape::world_bound(self)
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)> {
+        Shape::ray_intersection(self, ray, test_alpha_texture)
+    }
+
+    fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool {
+        Shape::does_ray_intersect(self, ray, test_alpha_texture)
+    }
+}
+
+/// An instance of a shared primitive, such as a hierarchy of the triangles in
+/// a mesh, placed in the scene with its own transformation. Many instances can
+/// share one primitive, so the primitive's geometry and acceleration structure
+/// are only stored once. (p. 252)
+///
+/// Rays are transformed into the primitive's space before they are
+/// intersected with it, and the resulting interactions are transformed back
+/// to world space. The shared primitive can itself contain instances, which
+/// allows instances to be nested. The shape that a returned interaction refers
+/// to is the shape in the primitive's space, so its `object_to_world`
+/// transformation doesn't include the instance's transformation.
+pub struct TransformedPrimitive<'a, P: ?Sized> {
+    primitive: &'a P,
+
+    /// The transformation from the primitive's space to world space, which
+    /// may change over time.
+    primitive_to_world: AnimatedTransform,
+}
+
+impl<'a, P: Primitive + ?Sized> TransformedPrimitive<'a, P> {
+    /// Creates an instance of `primitive` that is placed in the scene by the
+    /// possibly animated `primitive_to_world` transformation.
+    pub fn new(primitive: &'a P, primitive_to_world: AnimatedTransform) -> Self {
+        Self {
+            primitive,
+            primitive_to_world,
+        }
+    }
+
+    /// Creates an instance of `primitive` that is placed in the scene by a
+    /// transformation that doesn't change over time.
+    pub fn from_transform(primitive: &'a P, primitive_to_world: transform::Transform) -> Self {
+        Self::new(
+            primitive,
+            AnimatedTransform::new(primitive_to_world, 0.0, primitive_to_world, 1.0),
+        )
+    }
+}
+
+impl<'a, P: Primitive + ?Sized> Primitive for TransformedPrimitive<'a, P> {
+    fn world_bound(&self) -> Bounds3<f32> {
+        self.primitive_to_world
+            .motion_bounds(&self.primitive.world_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &Ray,
+        test_alpha_texture: bool,
+    ) -> Option<(f32, SurfaceInteraction)> {
+        let primitive_to_world = self.primitive_to_world.interpolate(ray.time);
+        let ray = primitive_to_world.inverse().transform(ray);
+        let (t_hit, interaction) = self.primitive.ray_intersection(&ray, test_alpha_texture)?;
+        Some((t_hit, primitive_to_world.transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &Ray, test_alpha_texture: bool) -> bool {
+        let primitive_to_world = self.primitive_to_world.interpolate(ray.time);
+        let ray = primitive_to_world.inverse().transform(ray);
+        self.primitive.does_ray_intersect(&ray, test_alpha_texture)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::medium;
 use crate::transform;
-use cgmath::Transform;
+use crate::transform::ApplyTransformWithError;
+use cgmath::InnerSpace;
 
 #[derive(Clone, Copy)]
 pub struct Ray {
@@ -12,8 +13,9 @@ pub struct Ray {
     /// Limits the ray to a finite segment.
     pub t_max: f32,
 
-    /// A time value used when rendering an animated scene.
-    /// Not really sure why this is necessary. From p. 73.
+    /// The scene time at which the ray is traced. Animated transformations are
+    /// interpolated to this time, so rays traced at different times within the
+    /// camera's shutter interval produce motion blur. (p. 73)
     pub time: f32,
 
     /// The medium containing the ray's origin. This is used in accounting for
@@ -23,7 +25,7 @@ pub struct Ray {
 
 impl Ray {
     /// Get the position along the ray for a given value for the parameter, t.
-    fn at_t(&self, t: f32) -> cgmath::Point3<f32> {
+    pub fn at_t(&self, t: f32) -> cgmath::Point3<f32> {
         self.origin + self.direction * t
     }
 }
@@ -40,16 +42,68 @@ impl Default for Ray {
     }
 }
 
-impl transform::Transform<Ray> for cgmath::Matrix4<f32> {
+impl transform::ApplyTransform<Ray> for transform::Transform {
     fn transform(&self, ray: &Ray) -> Ray {
-        Ray {
-            // FIXME: Deal with round-off error in point transformation. (p. 95)
-            origin: self.transform_point(ray.origin),
-            direction: self.transform_vector(ray.direction),
-            t_max: ray.t_max,
-            time: ray.time,
-            medium: ray.medium,
-        }
+        let (transformed, _) = self.transform_with_error(ray);
+        transformed
+    }
+}
+
+impl transform::ApplyTransformWithError<Ray> for transform::Transform {
+    /// Bounds on the error in the ray's origin and direction, respectively.
+    type ErrorBound = (cgmath::Vector3<f32>, cgmath::Vector3<f32>);
+
+    /// Transforms the ray and returns bounds on the error in the transformed
+    /// ray's origin and direction.
+    ///
+    /// The transformed origin is offset to the far edge of its error bound,
+    /// along the ray's direction, so that the ray's origin is guaranteed to lie
+    /// in front of the surface it may have been spawned from. `t_max` is
+    /// shortened by the same amount so that the ray's endpoint is unchanged.
+    /// (p. 233)
+    fn transform_with_error(
+        &self,
+        ray: &Ray,
+    ) -> (Ray, (cgmath::Vector3<f32>, cgmath::Vector3<f32>)) {
+        let (origin, o_err) = self.transform_with_error(&ray.origin);
+        let (direction, d_err) = self.transform_with_error(&ray.direction);
+        (offset_ray(ray, origin, o_err, direction), (o_err, d_err))
+    }
+
+    fn transform_with_error_bound(
+        &self,
+        ray: &Ray,
+        (o_err, d_err): &(cgmath::Vector3<f32>, cgmath::Vector3<f32>),
+    ) -> (Ray, (cgmath::Vector3<f32>, cgmath::Vector3<f32>)) {
+        let (origin, o_err) = self.transform_with_error_bound(&ray.origin, o_err);
+        let (direction, d_err) = self.transform_with_error_bound(&ray.direction, d_err);
+        (offset_ray(ray, origin, o_err, direction), (o_err, d_err))
+    }
+}
+
+/// Returns a copy of `ray` with the given transformed origin and direction,
+/// where the origin is offset to the edge of its error bound along the
+/// direction.
+fn offset_ray(
+    ray: &Ray,
+    origin: cgmath::Point3<f32>,
+    o_err: cgmath::Vector3<f32>,
+    direction: cgmath::Vector3<f32>,
+) -> Ray {
+    let mut origin = origin;
+    let mut t_max = ray.t_max;
+    let length_squared = direction.magnitude2();
+    if length_squared > 0.0 {
+        let dt = direction.map(|f| f.abs()).dot(o_err) / length_squared;
+        origin += direction * dt;
+        t_max -= dt;
+    }
+    Ray {
+        origin,
+        direction,
+        t_max,
+        time: ray.time,
+        medium: ray.medium,
     }
 }
 
@@ -105,17 +159,17 @@ impl Default for RayDifferential {
     }
 }
 
-impl transform::Transform<RayDifferential> for cgmath::Matrix4<f32> {
+impl transform::ApplyTransform<RayDifferential> for transform::Transform {
     fn transform(&self, rd: &RayDifferential) -> RayDifferential {
         RayDifferential {
             primary: self.transform(&rd.primary),
             has_differentials: rd.has_differentials,
             // FIXME: Deal with round-off error in point transformation.
-            dx_origin: self.transform_point(rd.dx_origin),
-            dx_direction: self.transform_vector(rd.dx_direction),
+            dx_origin: self.transform(&rd.dx_origin),
+            dx_direction: self.transform(&rd.dx_direction),
             // FIXME: Deal with round-off error in point transformation.
-            dy_origin: self.transform_point(rd.dy_origin),
-            dy_direction: self.transform_vector(rd.dy_direction),
+            dy_origin: self.transform(&rd.dy_origin),
+            dy_direction: self.transform(&rd.dy_direction),
         }
     }
 }
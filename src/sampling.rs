@@ -0,0 +1,349 @@
+use crate::math;
+use cgmath::InnerSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+
+/// The largest `f32` that is less than one. Sampled values are clamped to it
+/// so that they stay inside the half-open range [0, 1).
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Samples the linear function on [0, 1] that has the value `a` at 0 and `b`
+/// at 1, by inverting its cumulative distribution at the uniform sample `u`.
+pub fn sample_linear(u: f32, a: f32, b: f32) -> f32 {
+    if u == 0.0 && a == 0.0 {
+        return 0.0;
+    }
+    let x = u * (a + b) / (a + ((1.0 - u) * a * a + u * b * b).sqrt());
+    x.min(ONE_MINUS_EPSILON)
+}
+
+/// Samples the bilinear function on [0, 1]^2 whose values at the corners
+/// (0, 0), (1, 0), (0, 1) and (1, 1) are given by `w`. The second dimension is
+/// sampled from its marginal distribution, and then the first is sampled from
+/// its conditional distribution.
+pub fn sample_bilinear(u: Point2<f32>, w: [f32; 4]) -> Point2<f32> {
+    let y = sample_linear(u.y, w[0] + w[1], w[2] + w[3]);
+    let x = sample_linear(
+        u.x,
+        (1.0 - y) * w[0] + y * w[2],
+        (1.0 - y) * w[1] + y * w[3],
+    );
+    Point2::new(x, y)
+}
+
+/// Returns the probability density of sampling the point `p` with
+/// `sample_bilinear` for the corner values `w`.
+pub fn bilinear_pdf(p: Point2<f32>, w: [f32; 4]) -> f32 {
+    if p.x < 0.0 || p.x > 1.0 || p.y < 0.0 || p.y > 1.0 {
+        return 0.0;
+    }
+    let sum = w[0] + w[1] + w[2] + w[3];
+    if sum == 0.0 {
+        return 1.0;
+    }
+    4.0 * ((1.0 - p.x) * (1.0 - p.y) * w[0]
+        + p.x * (1.0 - p.y) * w[1]
+        + (1.0 - p.x) * p.y * w[2]
+        + p.x * p.y * w[3])
+        / sum
+}
+
+/// Samples barycentric coordinates uniformly over a triangle. Returns the
+/// first two coordinates; the third is one minus their sum. (p. 781)
+pub fn uniform_sample_triangle(u: Point2<f32>) -> (f32, f32) {
+    let su0 = u.x.sqrt();
+    (1.0 - su0, u.y * su0)
+}
+
+/// Returns the probability density, with respect to solid angle, of sampling
+/// a direction uniformly within a cone whose half-angle has the cosine
+/// `cos_theta_max`. (p. 781)
+pub fn uniform_cone_pdf(cos_theta_max: f32) -> f32 {
+    1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max))
+}
+
+/// Returns the solid angle subtended by the spherical triangle whose vertices
+/// are the normalized directions `a`, `b` and `c`.
+pub fn spherical_triangle_area(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+    (2.0 * a
+        .dot(b.cross(c))
+        .atan2(1.0 + a.dot(b) + a.dot(c) + b.dot(c)))
+    .abs()
+}
+
+/// Samples a direction uniformly within the solid angle that the triangle
+/// with vertices `v` subtends from the point `p`, using Arvo's method.
+/// Returns the barycentric coordinates of the point on the triangle in that
+/// direction and the probability density of the direction with respect to
+/// solid angle. Returns `None` if the triangle is degenerate as seen from
+/// `p`.
+pub fn sample_spherical_triangle(
+    v: [Point3<f32>; 3],
+    p: Point3<f32>,
+    u: Point2<f32>,
+) -> Option<([f32; 3], f32)> {
+    // Compute vertices of the spherical triangle, as seen from p.
+    let a = (v[0] - p).normalize();
+    let b = (v[1] - p).normalize();
+    let c = (v[2] - p).normalize();
+
+    // Compute the normals of the planes through p and each edge of the
+    // spherical triangle, and the triangle's angles at its vertices.
+    let n_ab = a.cross(b);
+    let n_bc = b.cross(c);
+    let n_ca = c.cross(a);
+    if n_ab.magnitude2() == 0.0 || n_bc.magnitude2() == 0.0 || n_ca.magnitude2() == 0.0 {
+        return None;
+    }
+    let (n_ab, n_bc, n_ca) = (n_ab.normalize(), n_bc.normalize(), n_ca.normalize());
+    let alpha = angle_between(n_ab, -1.0 * n_ca);
+    let beta = angle_between(n_bc, -1.0 * n_ab);
+    let gamma = angle_between(n_ca, -1.0 * n_bc);
+
+    // Uniformly sample the area of the sub-triangle that is cut off by the
+    // sampled point c' on the edge from a to c.
+    let pi = std::f32::consts::PI;
+    let area_pi = alpha + beta + gamma;
+    let sub_area_pi = pi + u.x * (area_pi - pi);
+    let area = area_pi - pi;
+    if area <= 0.0 {
+        return None;
+    }
+    let pdf = 1.0 / area;
+
+    // Find cos(beta') for the point c' along the arc between a and c.
+    let (sin_alpha, cos_alpha) = alpha.sin_cos();
+    let (sin_sub_area, cos_sub_area) = sub_area_pi.sin_cos();
+    let sin_phi = sin_sub_area * cos_alpha - cos_sub_area * sin_alpha;
+    let cos_phi = cos_sub_area * cos_alpha + sin_sub_area * sin_alpha;
+    let k1 = cos_phi + cos_alpha;
+    let k2 = sin_phi - sin_alpha * a.dot(b);
+    let cos_bp = (k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha)
+        / ((k2 * sin_phi + k1 * cos_phi) * sin_alpha);
+    let cos_bp = math::clamp(cos_bp, -1.0, 1.0);
+    let sin_bp = (1.0 - cos_bp * cos_bp).max(0.0).sqrt();
+    let c_prime = cos_bp * a + sin_bp * gram_schmidt(c, a).normalize();
+
+    // Sample the direction along the arc between b and c'.
+    let cos_theta = 1.0 - u.y * (1.0 - c_prime.dot(b));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let w = cos_theta * b + sin_theta * gram_schmidt(c_prime, b).normalize();
+
+    // Find the barycentric coordinates of the point on the triangle in the
+    // sampled direction.
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let s1 = w.cross(e2);
+    let divisor = s1.dot(e1);
+    if divisor == 0.0 {
+        // The triangle is seen edge-on, so any point on it will do.
+        return Some(([1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0], pdf));
+    }
+    let inv_divisor = 1.0 / divisor;
+    let s = p - v[0];
+    let b1 = math::clamp(s.dot(s1) * inv_divisor, 0.0, 1.0);
+    let b2 = math::clamp(w.dot(s.cross(e1)) * inv_divisor, 0.0, 1.0);
+    let (b1, b2) = if b1 + b2 > 1.0 {
+        (b1 / (b1 + b2), b2 / (b1 + b2))
+    } else {
+        (b1, b2)
+    };
+    Some(([1.0 - b1 - b2, b1, b2], pdf))
+}
+
+/// Returns the angle between the normalized vectors `v1` and `v2`, computed
+/// in a way that stays accurate when the vectors are nearly parallel.
+fn angle_between(v1: Vector3<f32>, v2: Vector3<f32>) -> f32 {
+    if v1.dot(v2) < 0.0 {
+        std::f32::consts::PI - 2.0 * ((v1 + v2).magnitude() / 2.0).min(1.0).asin()
+    } else {
+        2.0 * ((v2 - v1).magnitude() / 2.0).min(1.0).asin()
+    }
+}
+
+/// Returns the component of `v` that is perpendicular to the normalized
+/// vector `w`.
+fn gram_schmidt(v: Vector3<f32>, w: Vector3<f32>) -> Vector3<f32> {
+    v - v.dot(w) * w
+}
+
+/// A piecewise-constant distribution over the range [0, 1], defined by the
+/// function values of its equally sized pieces. (p. 758)
+pub struct Distribution1D {
+    func: Vec<f32>,
+
+    /// The cumulative distribution function at the boundaries of the pieces,
+    /// which has one more element than `func`.
+    cdf: Vec<f32>,
+
+    /// The integral of the function over [0, 1].
+    func_int: f32,
+}
+
+impl Distribution1D {
+    /// Creates the distribution for the given function values, which must not
+    /// be empty or negative. If the values are all zero, every piece is equally
+    /// likely.
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i] / n as f32);
+        }
+
+        // Transform the step function integral into a cumulative distribution
+        // function.
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if func_int == 0.0 {
+                i as f32 / n as f32
+            } else {
+                *c / func_int
+            };
+        }
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    /// Returns the number of pieces in the distribution.
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Returns the integral of the function over [0, 1].
+    pub fn func_int(&self) -> f32 {
+        self.func_int
+    }
+
+    /// Picks a piece of the distribution with probability proportional to its
+    /// function value. Returns the piece's index, the probability of picking
+    /// it, and the position of `u` within the piece's range of the cumulative
+    /// distribution function, which can be used as a new uniform sample.
+    /// (p. 761)
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32, f32) {
+        // Find the last piece that starts at or below u.
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let u_remapped = if width > 0.0 {
+            ((u - self.cdf[offset]) / width).min(ONE_MINUS_EPSILON)
+        } else {
+            0.0
+        };
+        (offset, self.discrete_pdf(offset), u_remapped)
+    }
+
+    /// Returns the probability of picking the piece at `index` with
+    /// `sample_discrete`.
+    pub fn discrete_pdf(&self, index: usize) -> f32 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sample_spherical_triangle;
+    use super::spherical_triangle_area;
+    use super::Distribution1D;
+    use cgmath::EuclideanSpace;
+    use cgmath::InnerSpace;
+    use cgmath::Point2;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    #[test]
+    fn discrete_pdf_is_proportional_to_function() {
+        let distribution = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert_eq!(distribution.count(), 4);
+        assert_eq!(distribution.func_int(), 2.0);
+        for (index, &expected) in [0.125, 0.375, 0.0, 0.5].iter().enumerate() {
+            assert!((distribution.discrete_pdf(index) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn sample_discrete_inverts_cdf() {
+        let distribution = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        let cases = [
+            (0.0, 0, 0.0),
+            (0.0625, 0, 0.5),
+            (0.125, 1, 0.0),
+            (0.3125, 1, 0.5),
+            // The piece with a zero value is never picked.
+            (0.5, 3, 0.0),
+            (0.75, 3, 0.5),
+        ];
+        for &(u, expected_index, expected_u_remapped) in cases.iter() {
+            let (index, pmf, u_remapped) = distribution.sample_discrete(u);
+            assert_eq!(index, expected_index, "u = {}", u);
+            assert_eq!(pmf, distribution.discrete_pdf(index));
+            assert!((u_remapped - expected_u_remapped).abs() < 1e-6, "u = {}", u);
+        }
+
+        let (index, _, u_remapped) = distribution.sample_discrete(super::ONE_MINUS_EPSILON);
+        assert_eq!(index, 3);
+        assert!(u_remapped < 1.0);
+    }
+
+    #[test]
+    fn sample_discrete_handles_zero_function() {
+        let distribution = Distribution1D::new(vec![0.0, 0.0]);
+        assert_eq!(distribution.discrete_pdf(0), 0.5);
+        assert_eq!(distribution.sample_discrete(0.75), (1, 0.5, 0.5));
+    }
+
+    #[test]
+    fn spherical_triangle_area_of_octant() {
+        let area = spherical_triangle_area(
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        assert!((area - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_spherical_triangle_is_uniform() {
+        let v = [
+            Point3::new(0.3, -0.2, 0.1),
+            Point3::new(2.3, -0.2, 0.6),
+            Point3::new(0.3, 1.3, 0.1),
+        ];
+        let p = Point3::new(0.5, 0.7, 2.0);
+        let direction = |q: Point3<f32>| (q - p).normalize();
+        let area = spherical_triangle_area(direction(v[0]), direction(v[1]), direction(v[2]));
+
+        // The triangle is split at the midpoint m of the edge from v[1] to
+        // v[2]. Points in the sub-triangle (v[0], v[1], m) have b[2] <= b[1],
+        // and a uniform distribution puts them there in proportion to the
+        // sub-triangle's solid angle.
+        let m = Point3::from_vec(0.5 * (v[1].to_vec() + v[2].to_vec()));
+        let sub_area = spherical_triangle_area(direction(v[0]), direction(v[1]), direction(m));
+
+        let n = 256;
+        let mut in_sub_triangle = 0;
+        for i in 0..n * n {
+            let u = Point2::new(
+                ((i % n) as f32 + 0.5) / n as f32,
+                ((i / n) as f32 + 0.5) / n as f32,
+            );
+            let (b, pdf) = sample_spherical_triangle(v, p, u).unwrap();
+            assert!((pdf - 1.0 / area).abs() < 1e-3 / area);
+            assert!(b.iter().all(|&b| b >= 0.0));
+            assert!((b[0] + b[1] + b[2] - 1.0).abs() < 1e-5);
+            if b[2] <= b[1] {
+                in_sub_triangle += 1;
+            }
+        }
+        let fraction = in_sub_triangle as f32 / (n * n) as f32;
+        assert!((fraction - sub_area / area).abs() < 1e-2);
+    }
+}
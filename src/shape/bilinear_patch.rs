@@ -0,0 +1,832 @@
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box::Bounds3;
+use crate::interaction::Interaction;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::sampling;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use crate::vector;
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point2, Point3, Vector3};
+
+/// The distance, as a fraction of the lengths of a patch's diagonals, above a
+/// point on the patch from which a ray is traced back to the patch to find the
+/// point's parametric coordinates.
+const PDF_RAY_OFFSET: f32 = 1e-3;
+
+/// A mesh of bilinear patches.
+pub struct BilinearPatchMesh<'a> {
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    /// The vertices in world space that make up the mesh.
+    world_space_vertices: Vec<Point3<f32>>,
+
+    /// An array that describes each patch in the mesh. Each element of the
+    /// array is a tuple that contains four indices into the `vertices` array,
+    /// for the patch's corners at (u, v) = (0, 0), (1, 0), (0, 1) and (1, 1).
+    patch_vertex_indices: Vec<(usize, usize, usize, usize)>,
+
+    /// An array containing a normal vector for each vertex in the mesh.
+    normals: Option<Vec<Vector3<f32>>>,
+
+    /// An array containing a UV coordinate for each vertex in the mesh.
+    uvs: Option<Vec<Point2<f32>>>,
+}
+
+/// A reference to an individual bilinear patch in a mesh.
+///
+/// The patch's surface is the bilinear interpolation of its four corner
+/// vertices, p(u, v) = (1-u)(1-v) p00 + u(1-v) p10 + (1-u)v p01 + uv p11. Unlike
+/// a pair of triangles, the surface doesn't need to be planar.
+pub struct BilinearPatch<'a> {
+    mesh: &'a BilinearPatchMesh<'a>,
+    index_in_mesh: usize,
+}
+
+impl<'a> BilinearPatch<'a> {
+    /// Returns the patch's corner vertices in world space, in the order p00,
+    /// p10, p01, p11.
+    pub fn world_space_vertices(&self) -> (Point3<f32>, Point3<f32>, Point3<f32>, Point3<f32>) {
+        let (i00, i10, i01, i11) = self.mesh.patch_vertex_indices[self.index_in_mesh];
+        let vertices = &self.mesh.world_space_vertices;
+        (vertices[i00], vertices[i10], vertices[i01], vertices[i11])
+    }
+
+    pub fn object_space_vertices(&self) -> (Point3<f32>, Point3<f32>, Point3<f32>, Point3<f32>) {
+        let world_to_object = self.world_to_object();
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        (
+            world_to_object.transform(&p00),
+            world_to_object.transform(&p10),
+            world_to_object.transform(&p01),
+            world_to_object.transform(&p11),
+        )
+    }
+
+    /// Returns the UV coordinates at the patch's corners. If the mesh does not
+    /// have UV coordinates then the patch's (u, v) parameterization is used.
+    pub fn uvs(&self) -> (Point2<f32>, Point2<f32>, Point2<f32>, Point2<f32>) {
+        match &self.mesh.uvs {
+            Some(uvs) => {
+                let (i00, i10, i01, i11) = self.mesh.patch_vertex_indices[self.index_in_mesh];
+                (uvs[i00], uvs[i10], uvs[i01], uvs[i11])
+            }
+            None => (
+                Point2::new(0.0, 0.0),
+                Point2::new(1.0, 0.0),
+                Point2::new(0.0, 1.0),
+                Point2::new(1.0, 1.0),
+            ),
+        }
+    }
+
+    /// Returns a flag that indicates whether the patch is a planar rectangle.
+    /// Rectangles have a constant `dpdu` and `dpdv`, so their area and area
+    /// sampling are simpler.
+    pub fn is_rectangle(&self) -> bool {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        if p00 == p01 || p01 == p11 || p11 == p10 || p10 == p00 {
+            return false;
+        }
+
+        // Check that the patch is planar.
+        let n = (p10 - p00).cross(p01 - p00).normalize();
+        if (p11 - p00).dot(n).abs() > 1e-5 {
+            return false;
+        }
+
+        // A planar quadrilateral whose corners are equidistant from its
+        // center is a rectangle.
+        let center =
+            Point3::from_vec((p00.to_vec() + p01.to_vec() + p10.to_vec() + p11.to_vec()) / 4.0);
+        let d2 = [
+            p00.distance2(center),
+            p01.distance2(center),
+            p10.distance2(center),
+            p11.distance2(center),
+        ];
+        d2[1..].iter().all(|d| (d - d2[0]).abs() / d2[0] <= 1e-4)
+    }
+
+    /// Returns the corner values of the bilinear distribution that non-
+    /// rectangular patches are sampled with, which are the patch's
+    /// differential areas at its corners.
+    fn area_sampling_weights(&self) -> [f32; 4] {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        [
+            (p10 - p00).cross(p01 - p00).magnitude(),
+            (p10 - p00).cross(p11 - p10).magnitude(),
+            (p01 - p00).cross(p11 - p01).magnitude(),
+            (p11 - p10).cross(p11 - p01).magnitude(),
+        ]
+    }
+
+    /// Returns the normalized per-vertex normal interpolated to the point with
+    /// parametric coordinates `uv`. Returns `None` if the mesh doesn't have
+    /// per-vertex normals or if the interpolated normal vanishes.
+    fn interpolated_normal(&self, uv: Point2<f32>) -> Option<Vector3<f32>> {
+        let normals = self.mesh.normals.as_ref()?;
+        let (i00, i10, i01, i11) = self.mesh.patch_vertex_indices[self.index_in_mesh];
+        let ns = bilerp(uv, normals[i00], normals[i10], normals[i01], normals[i11]);
+        if ns.magnitude2() > 0.0 {
+            Some(ns.normalize())
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> BilinearPatchMesh<'a> {
+    pub fn patch_at(&'a self, index: usize) -> BilinearPatch<'a> {
+        BilinearPatch {
+            mesh: self,
+            index_in_mesh: index,
+        }
+    }
+
+    /// Returns the number of patches in the mesh.
+    pub fn patch_count(&self) -> usize {
+        self.patch_vertex_indices.len()
+    }
+
+    /// Returns an iterator over references to each patch in the mesh.
+    pub fn patches(&'a self) -> impl Iterator<Item = BilinearPatch<'a>> {
+        (0..self.patch_count()).map(move |i| self.patch_at(i))
+    }
+}
+
+/// An error that occurs when a `BilinearPatchMesh` is built from inconsistent
+/// data.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BilinearPatchMeshError {
+    /// A patch refers to a vertex index that is outside the vertex array.
+    VertexIndexOutOfBounds {
+        patch: usize,
+        vertex_index: usize,
+        vertex_count: usize,
+    },
+
+    /// A per-vertex attribute array does not contain exactly one element for
+    /// each vertex in the mesh.
+    AttributeLengthMismatch {
+        attribute: &'static str,
+        attribute_count: usize,
+        vertex_count: usize,
+    },
+}
+
+impl std::fmt::Display for BilinearPatchMeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BilinearPatchMeshError::VertexIndexOutOfBounds {
+                patch,
+                vertex_index,
+                vertex_count,
+            } => write!(
+                f,
+                "patch {} refers to vertex {} but the mesh has only {} vertices",
+                patch, vertex_index, vertex_count
+            ),
+            BilinearPatchMeshError::AttributeLengthMismatch {
+                attribute,
+                attribute_count,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh has {} {} but {} vertices",
+                attribute_count, attribute, vertex_count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BilinearPatchMeshError {}
+
+pub struct BilinearPatchMeshBuilder<'a> {
+    object_to_world: &'a transform::Transform,
+    reverse_orientation: bool,
+    object_space_vertices: Vec<Point3<f32>>,
+    patch_vertex_indices: Vec<(usize, usize, usize, usize)>,
+    normals: Option<Vec<Vector3<f32>>>,
+    uvs: Option<Vec<Point2<f32>>>,
+}
+
+impl<'a> BilinearPatchMeshBuilder<'a> {
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        object_space_vertices: Vec<Point3<f32>>,
+        patch_vertex_indices: Vec<(usize, usize, usize, usize)>,
+    ) -> Self {
+        Self {
+            object_to_world,
+            reverse_orientation,
+            object_space_vertices,
+            patch_vertex_indices,
+            normals: None,
+            uvs: None,
+        }
+    }
+
+    pub fn normals(mut self, normals: Vec<Vector3<f32>>) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn uvs(mut self, uvs: Vec<Point2<f32>>) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Builds the bilinear patch mesh, transforming its vertices and normals
+    /// into world space.
+    ///
+    /// Returns an error if any patch refers to a vertex that doesn't exist or
+    /// if any per-vertex attribute array doesn't contain exactly one element
+    /// per vertex.
+    pub fn build(self) -> Result<BilinearPatchMesh<'a>, BilinearPatchMeshError> {
+        let vertex_count = self.object_space_vertices.len();
+
+        for (patch, &(i00, i10, i01, i11)) in self.patch_vertex_indices.iter().enumerate() {
+            for &vertex_index in [i00, i10, i01, i11].iter() {
+                if vertex_index >= vertex_count {
+                    return Err(BilinearPatchMeshError::VertexIndexOutOfBounds {
+                        patch,
+                        vertex_index,
+                        vertex_count,
+                    });
+                }
+            }
+        }
+
+        check_attribute_length("normals", &self.normals, vertex_count)?;
+        check_attribute_length("uvs", &self.uvs, vertex_count)?;
+
+        let object_to_world = self.object_to_world;
+        let reverse_orientation = self.reverse_orientation;
+        let world_space_vertices = self
+            .object_space_vertices
+            .into_iter()
+            .map(|p| object_to_world.transform(&p))
+            .collect();
+
+        // Unlike vertices, normals are interpolated in world space, so they
+        // are transformed up front as well. The normals are reversed along
+        // with the patch's orientation.
+        let normals = self.normals.map(|normals| {
+            normals
+                .into_iter()
+                .map(|n| {
+                    let n = object_to_world.transform(&vector::Normal3(n)).0;
+                    if reverse_orientation {
+                        -1.0 * n
+                    } else {
+                        n
+                    }
+                })
+                .collect()
+        });
+
+        Ok(BilinearPatchMesh {
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            world_space_vertices,
+            patch_vertex_indices: self.patch_vertex_indices,
+            normals,
+            uvs: self.uvs,
+        })
+    }
+}
+
+/// Returns an error if the optional per-vertex attribute array doesn't contain
+/// exactly one element per vertex.
+fn check_attribute_length<T>(
+    attribute: &'static str,
+    values: &Option<Vec<T>>,
+    vertex_count: usize,
+) -> Result<(), BilinearPatchMeshError> {
+    match values {
+        Some(values) if values.len() != vertex_count => {
+            Err(BilinearPatchMeshError::AttributeLengthMismatch {
+                attribute,
+                attribute_count: values.len(),
+                vertex_count,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+impl<'a> Shape for BilinearPatch<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.mesh.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.mesh.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.mesh.reverse_orientation
+    }
+
+    fn object_bound(&self) -> Bounds3<f32> {
+        let (p00, p10, p01, p11) = self.object_space_vertices();
+        Bounds3::from_corners(p00, p10).union(&Bounds3::from_corners(p01, p11))
+    }
+
+    fn world_bound(&self) -> Bounds3<f32> {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        Bounds3::from_corners(p00, p10).union(&Bounds3::from_corners(p01, p11))
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        let (t, uv) = bilinear_intersection(ray, p00, p10, p01, p11)?;
+
+        // Compute bilinear patch point, dpdu and dpdv at the hit's (u, v).
+        let (p_hit, dpdu, dpdv) = bilinear_point_and_derivatives(uv, p00, p10, p01, p11);
+
+        // Compute the texture coordinates at the hit. When the mesh provides
+        // UV coordinates, the partial derivatives are re-expressed with
+        // respect to them. (s, t) are the texture coordinates.
+        let (uv00, uv10, uv01, uv11) = self.uvs();
+        let uv_hit = bilinear_uv(uv, (uv00, uv10, uv01, uv11));
+        let (duds, dudt, dvds, dvdt) = if self.mesh.uvs.is_some() {
+            let dstdu = bilerp_edge_u(
+                uv.y,
+                uv00.to_vec(),
+                uv10.to_vec(),
+                uv01.to_vec(),
+                uv11.to_vec(),
+            );
+            let dstdv = bilerp_edge_v(
+                uv.x,
+                uv00.to_vec(),
+                uv10.to_vec(),
+                uv01.to_vec(),
+                uv11.to_vec(),
+            );
+            (
+                safe_inverse(dstdu.x),
+                safe_inverse(dstdu.y),
+                safe_inverse(dstdv.x),
+                safe_inverse(dstdv.y),
+            )
+        } else {
+            (1.0, 0.0, 0.0, 1.0)
+        };
+        let (dpdu, dpdv) = if self.mesh.uvs.is_some() {
+            let dpds = dpdu * duds + dpdv * dvds;
+            let dpdt = dpdu * dudt + dpdv * dvdt;
+            if dpds.cross(dpdt).magnitude2() > 0.0 {
+                // Keep the orientation given by the patch's own
+                // parameterization.
+                let dpdt = if dpdu.cross(dpdv).dot(dpds.cross(dpdt)) < 0.0 {
+                    -1.0 * dpdt
+                } else {
+                    dpdt
+                };
+                (dpds, dpdt)
+            } else {
+                (dpdu, dpdv)
+            }
+        } else {
+            (dpdu, dpdv)
+        };
+        if dpdu.cross(dpdv).magnitude2() == 0.0 {
+            // The patch is degenerate at the hit point.
+            return None;
+        }
+
+        // Find partial derivatives dndu and dndv for bilinear patch. The
+        // second derivatives with respect to a single parameter vanish.
+        let d2pduu = Vector3::new(0.0, 0.0, 0.0);
+        let d2pdvv = Vector3::new(0.0, 0.0, 0.0);
+        let d2pduv = (p00 - p01) + (p11 - p10);
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+        let (dndu, dndv) = if self.mesh.uvs.is_some() {
+            (dndu * duds + dndv * dvds, dndu * dudt + dndv * dvdt)
+        } else {
+            (dndu, dndv)
+        };
+
+        // Compute error bounds for bilinear patch intersection.
+        let p_error = math::gamma(6) * corner_abs_sum(p00, p10, p01, p11);
+
+        let mut interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            uv_hit,
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        // Initialize bilinear patch shading geometry from the interpolated
+        // per-vertex normals, if present.
+        if let Some(ns) = self.interpolated_normal(uv) {
+            let normals = self.mesh.normals.as_ref().unwrap();
+            let (i00, i10, i01, i11) = self.mesh.patch_vertex_indices[self.index_in_mesh];
+            let (n00, n10, n01, n11) = (normals[i00], normals[i10], normals[i01], normals[i11]);
+            let dndu = bilerp_edge_u(uv.y, n00, n10, n01, n11);
+            let dndv = bilerp_edge_v(uv.x, n00, n10, n01, n11);
+            let (dndu, dndv) = if self.mesh.uvs.is_some() {
+                (dndu * duds + dndv * dvds, dndu * dudt + dndv * dvdt)
+            } else {
+                (dndu, dndv)
+            };
+
+            // Build a shading tangent frame around the shading normal, as is
+            // done for triangles.
+            let ss = dpdu.normalize();
+            let ts = ss.cross(ns);
+            let (ss, ts) = if ts.magnitude2() > 0.0 {
+                let ts = ts.normalize();
+                (ts.cross(ns), ts)
+            } else {
+                vector::coordinate_system(ns)
+            };
+            interaction.set_shading_geometry(ss, ts, dndu, dndv, true);
+        }
+
+        Some((t, interaction))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, _test_alpha_texture: bool) -> bool {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        bilinear_intersection(ray, p00, p10, p01, p11).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        if self.is_rectangle() {
+            return p00.distance(p01) * p00.distance(p10);
+        }
+
+        // Approximate the area of a general patch by splitting it into a grid
+        // of quadrilaterals and summing their areas.
+        const N: usize = 3;
+        let grid_point = |i: usize, j: usize| {
+            let uv = Point2::new(i as f32 / N as f32, j as f32 / N as f32);
+            bilinear_point_and_derivatives(uv, p00, p10, p01, p11).0
+        };
+        let mut area = 0.0;
+        for i in 0..N {
+            for j in 0..N {
+                let diagonal0 = grid_point(i + 1, j + 1) - grid_point(i, j);
+                let diagonal1 = grid_point(i + 1, j) - grid_point(i, j + 1);
+                area += 0.5 * diagonal0.cross(diagonal1).magnitude();
+            }
+        }
+        area
+    }
+
+    /// Samples a point on the patch's surface from the uniform sample `u`.
+    ///
+    /// Rectangles are sampled uniformly. Other patches are sampled with a
+    /// bilinear distribution that approximates the differential area across
+    /// the patch, and the returned density accounts for the approximation.
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+
+        // Sample bilinear patch parametric (u, v) coordinates.
+        let (uv, uv_pdf) = if self.is_rectangle() {
+            (u, 1.0)
+        } else {
+            let w = self.area_sampling_weights();
+            let uv = sampling::sample_bilinear(u, w);
+            (uv, sampling::bilinear_pdf(uv, w))
+        };
+
+        // Compute bilinear patch geometric quantities at sampled (u, v).
+        let (p, dpdu, dpdv) = bilinear_point_and_derivatives(uv, p00, p10, p01, p11);
+        let area_scale = dpdu.cross(dpdv).magnitude();
+        if area_scale == 0.0 {
+            return None;
+        }
+
+        // Compute surface normal for sampled bilinear patch (u, v). Per-vertex
+        // normals are authoritative when present.
+        let n = dpdu.cross(dpdv).normalize();
+        let n = match self.interpolated_normal(uv) {
+            Some(ns) => vector::face_forward(n, ns),
+            None if self.reverse_orientation() ^ self.object_to_world_swaps_handedness() => {
+                -1.0 * n
+            }
+            None => n,
+        };
+
+        let p_error = math::gamma(6) * corner_abs_sum(p00, p10, p01, p11);
+        let uv_texture = bilinear_uv(uv, self.uvs());
+        Some((
+            SurfacePoint::new(p, p_error, n, uv_texture),
+            uv_pdf / area_scale,
+        ))
+    }
+
+    /// Returns the density of sampling the point with `sample_area`. The
+    /// density on a non-rectangular patch depends on the point's parametric
+    /// (u, v) coordinates, which are found by intersecting the patch with a
+    /// short ray through the point along its normal.
+    fn pdf_area(&self, point: &dyn Interaction) -> f32 {
+        let (p00, p10, p01, p11) = self.world_space_vertices();
+        if self.is_rectangle() {
+            return 1.0 / self.surface_area();
+        }
+
+        let n = match point.normal() {
+            Some(n) => n,
+            None => return 0.0,
+        };
+        let offset = PDF_RAY_OFFSET * (p00.distance(p11) + p10.distance(p01));
+        let ray = crate::ray::Ray {
+            origin: point.point() + offset * n,
+            direction: -1.0 * n,
+            t_max: 2.0 * offset,
+            ..Default::default()
+        };
+        let uv = match bilinear_intersection(&ray, p00, p10, p01, p11) {
+            Some((_, uv)) => uv,
+            None => return 0.0,
+        };
+        let (_, dpdu, dpdv) = bilinear_point_and_derivatives(uv, p00, p10, p01, p11);
+        let area_scale = dpdu.cross(dpdv).magnitude();
+        if area_scale == 0.0 {
+            return 0.0;
+        }
+        sampling::bilinear_pdf(uv, self.area_sampling_weights()) / area_scale
+    }
+}
+
+/// Intersects the ray with the bilinear patch with corners `p00`, `p10`, `p01`
+/// and `p11`, returning the parametric t value along the ray and the (u, v)
+/// coordinates of the nearest hit in the (0, `ray.t_max`) range.
+///
+/// The patch is the set of line segments between corresponding points on the
+/// edges p00-p10 and p01-p11. The u values of the segments that the ray meets
+/// are the roots of a quadratic, and v and t then follow in closed form.
+pub(super) fn bilinear_intersection(
+    ray: &crate::ray::Ray,
+    p00: Point3<f32>,
+    p10: Point3<f32>,
+    p01: Point3<f32>,
+    p11: Point3<f32>,
+) -> Option<(f32, Point2<f32>)> {
+    let ro = ray.origin;
+    let rd = ray.direction;
+
+    // Find quadratic coefficients for the distance from the ray to the u
+    // iso-lines.
+    let a = (p10 - p00).cross(p01 - p11).dot(rd);
+    let c = (p00 - ro).cross(rd).dot(p01 - p00);
+    let b = (p10 - ro).cross(rd).dot(p11 - p10) - (a + c);
+    let (u1, u2) = solve_quadratic(a, b, c)?;
+
+    // Compute an epsilon that ensures that accepted t values are greater
+    // than zero.
+    let max_abs = |v: Vector3<f32>| v.x.abs().max(v.y.abs()).max(v.z.abs());
+    let eps = math::gamma(10)
+        * (max_abs(ro.to_vec())
+            + max_abs(rd)
+            + max_abs(p00.to_vec())
+            + max_abs(p10.to_vec())
+            + max_abs(p01.to_vec())
+            + max_abs(p11.to_vec()));
+
+    // Compute v and t for each u intersection and keep the nearest valid one.
+    let mut nearest: Option<(f32, Point2<f32>)> = None;
+    let candidate_us = if u1 == u2 { vec![u1] } else { vec![u1, u2] };
+    for u in candidate_us {
+        if !(0.0..=1.0).contains(&u) {
+            continue;
+        }
+
+        let uo = math::lerp_point(u, p00, p10);
+        let ud = math::lerp_point(u, p01, p11) - uo;
+        let deltao = uo - ro;
+        let perp = rd.cross(ud);
+        let p2 = perp.magnitude2();
+
+        // Compute the numerators of v and t with Cramer's rule.
+        let v_numerator = deltao.dot(rd.cross(perp));
+        let t_numerator = deltao.dot(ud.cross(perp));
+        if v_numerator < 0.0 || v_numerator > p2 || t_numerator <= p2 * eps {
+            continue;
+        }
+        let t = t_numerator / p2;
+        let t_nearest = nearest.map_or(ray.t_max, |(t, _)| t);
+        if t < t_nearest {
+            nearest = Some((t, Point2::new(u, v_numerator / p2)));
+        }
+    }
+    nearest
+}
+
+/// Solves a*t^2 + b*t + c = 0 in single precision, returning the roots with
+/// the smaller one first. A vanishing `a` gives the single root of the linear
+/// equation twice, which occurs for planar parallelograms.
+fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -1.0 * c / b;
+        return Some((t, t));
+    }
+
+    let discriminant = b as f64 * b as f64 - 4.0 * a as f64 * c as f64;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root_discriminant = discriminant.sqrt() as f32;
+    let q = if b < 0.0 {
+        -0.5 * (b - root_discriminant)
+    } else {
+        -0.5 * (b + root_discriminant)
+    };
+    let t0 = q / a;
+    let t1 = c / q;
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Returns the point on the bilinear patch at parametric coordinates `uv`,
+/// along with the partial derivatives of the position there.
+pub(super) fn bilinear_point_and_derivatives(
+    uv: Point2<f32>,
+    p00: Point3<f32>,
+    p10: Point3<f32>,
+    p01: Point3<f32>,
+    p11: Point3<f32>,
+) -> (Point3<f32>, Vector3<f32>, Vector3<f32>) {
+    let p = Point3::from_vec(bilerp(
+        uv,
+        p00.to_vec(),
+        p10.to_vec(),
+        p01.to_vec(),
+        p11.to_vec(),
+    ));
+    let dpdu = bilerp_edge_u(uv.y, p00.to_vec(), p10.to_vec(), p01.to_vec(), p11.to_vec());
+    let dpdv = bilerp_edge_v(uv.x, p00.to_vec(), p10.to_vec(), p01.to_vec(), p11.to_vec());
+    (p, dpdu, dpdv)
+}
+
+/// Returns the texture coordinates at parametric coordinates `uv` given the
+/// texture coordinates at the patch's corners.
+fn bilinear_uv(
+    uv: Point2<f32>,
+    (uv00, uv10, uv01, uv11): (Point2<f32>, Point2<f32>, Point2<f32>, Point2<f32>),
+) -> Point2<f32> {
+    Point2::from_vec(bilerp(
+        uv,
+        uv00.to_vec(),
+        uv10.to_vec(),
+        uv01.to_vec(),
+        uv11.to_vec(),
+    ))
+}
+
+/// Bilinearly interpolates the corner values at parametric coordinates `uv`.
+pub(super) fn bilerp<V: cgmath::VectorSpace<Scalar = f32>>(
+    uv: Point2<f32>,
+    v00: V,
+    v10: V,
+    v01: V,
+    v11: V,
+) -> V {
+    v00 * ((1.0 - uv.x) * (1.0 - uv.y))
+        + v10 * (uv.x * (1.0 - uv.y))
+        + v01 * ((1.0 - uv.x) * uv.y)
+        + v11 * (uv.x * uv.y)
+}
+
+/// Returns the partial derivative with respect to u of the bilinear
+/// interpolation of the corner values, at parameter value `v`.
+pub(super) fn bilerp_edge_u<V: cgmath::VectorSpace<Scalar = f32>>(
+    v: f32,
+    v00: V,
+    v10: V,
+    v01: V,
+    v11: V,
+) -> V {
+    (v10 * (1.0 - v) + v11 * v) - (v00 * (1.0 - v) + v01 * v)
+}
+
+/// Returns the partial derivative with respect to v of the bilinear
+/// interpolation of the corner values, at parameter value `u`.
+pub(super) fn bilerp_edge_v<V: cgmath::VectorSpace<Scalar = f32>>(
+    u: f32,
+    v00: V,
+    v10: V,
+    v01: V,
+    v11: V,
+) -> V {
+    (v01 * (1.0 - u) + v11 * u) - (v00 * (1.0 - u) + v10 * u)
+}
+
+/// Returns the inverse of `x`, or zero if `x` is too close to zero for its
+/// inverse to be meaningful.
+fn safe_inverse(x: f32) -> f32 {
+    if x.abs() < 1e-8 {
+        0.0
+    } else {
+        1.0 / x
+    }
+}
+
+/// Returns the component-wise sum of the absolute values of the patch's
+/// corners, which bounds the magnitude of any point on the patch.
+pub(super) fn corner_abs_sum(
+    p00: Point3<f32>,
+    p10: Point3<f32>,
+    p01: Point3<f32>,
+    p11: Point3<f32>,
+) -> Vector3<f32> {
+    let abs = |p: Point3<f32>| p.to_vec().map(|f| f.abs());
+    abs(p00) + abs(p10) + abs(p01) + abs(p11)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::BilinearPatchMeshBuilder;
+    use crate::sampling;
+    use crate::transform::Transform;
+    use cgmath::InnerSpace;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    #[test]
+    fn sample_area_matches_area() {
+        // The expected area of the curved patch is integrated with the
+        // midpoint rule.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let (p00, p10, p01, p11) = (
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.5),
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(1.3, 1.2, -0.4),
+        );
+        let mesh = BilinearPatchMeshBuilder::new(
+            &object_to_world,
+            false,
+            vec![p00, p10, p01, p11],
+            vec![(0, 1, 2, 3)],
+        )
+        .build()
+        .unwrap();
+        let n = 512;
+        let mut expected = 0.0;
+        for i in 0..n * n {
+            let u = ((i % n) as f32 + 0.5) / n as f32;
+            let v = ((i / n) as f32 + 0.5) / n as f32;
+            let dpdu = (1.0 - v) * (p10 - p00) + v * (p11 - p01);
+            let dpdv = (1.0 - u) * (p01 - p00) + u * (p11 - p10);
+            expected += dpdu.cross(dpdv).magnitude() / (n * n) as f32;
+        }
+        assert_area_sampling(&mesh.patch_at(0), expected);
+    }
+
+    #[test]
+    fn sample_solid_angle_matches_solid_angle() {
+        // The patch is a planar quadrilateral, so it subtends the same solid
+        // angle as the two triangles it can be split into.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let mesh = BilinearPatchMeshBuilder::new(
+            &object_to_world,
+            false,
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+                Point3::new(1.5, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2, 3)],
+        )
+        .build()
+        .unwrap();
+        let patch = mesh.patch_at(0);
+        let (p00, p10, p01, p11) = patch.world_space_vertices();
+        let reference = Point3::new(0.5, 0.7, 2.0);
+        let direction = |p: Point3<f32>| (p - reference).normalize();
+        let expected =
+            sampling::spherical_triangle_area(direction(p00), direction(p10), direction(p11))
+                + sampling::spherical_triangle_area(direction(p00), direction(p11), direction(p01));
+        assert_solid_angle_sampling(&patch, reference, expected);
+    }
+}
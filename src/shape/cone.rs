@@ -0,0 +1,215 @@
+use super::azimuth;
+use super::clipped_hit;
+use super::nearest_quadric_hit;
+use super::sampled_surface_point;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
+use crate::transform::SwapHandedness;
+use cgmath::EuclideanSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+use efloat::EFloat32;
+
+/// A cone centered on the z axis in object space, with its base on the z = 0
+/// plane and its apex on the positive z axis.
+pub struct Cone<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Cone-specific fields
+    height: f32,
+    radius: f32,
+    phi_max: f32,
+}
+
+impl<'a> Cone<'a> {
+    /// Creates a cone with a base of the given `radius` and its apex at
+    /// `height` along the z axis that is swept around the z axis up to
+    /// `phi_max` degrees.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        height: f32,
+        radius: f32,
+        phi_max: f32,
+    ) -> Self {
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Cone-specific fields
+            height,
+            radius,
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+        }
+    }
+}
+
+impl<'a> Shape for Cone<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(-1.0 * self.radius, -1.0 * self.radius, 0.0),
+            cgmath::Point3::new(self.radius, self.radius, self.height),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let (ray, (o_err, d_err)) = self.world_to_object().transform_with_error(ray);
+
+        // Initialize ray values.
+        let ox = EFloat32::new_with_err(ray.origin.x, o_err.x);
+        let oy = EFloat32::new_with_err(ray.origin.y, o_err.y);
+        let oz = EFloat32::new_with_err(ray.origin.z, o_err.z);
+        let dx = EFloat32::new_with_err(ray.direction.x, d_err.x);
+        let dy = EFloat32::new_with_err(ray.direction.y, d_err.y);
+        let dz = EFloat32::new_with_err(ray.direction.z, d_err.z);
+
+        // Compute quadratic cone coefficients.
+        let k = EFloat32::new(self.radius) / EFloat32::new(self.height);
+        let k = k * k;
+        let height = EFloat32::new(self.height);
+        let a = dx * dx + dy * dy - k * dz * dz;
+        let b = EFloat32::new(2.0) * (dx * ox + dy * oy - k * dz * (oz - height));
+        let c = ox * ox + oy * oy - k * (oz - height) * (oz - height);
+
+        // Solve quadratic equation for t values and find the nearest hit that
+        // hasn't been clipped away.
+        let (t0, t1) = math::quadratic(a, b, c)?;
+        let (t_shape_hit, (p_hit, phi)) = nearest_quadric_hit(t0, t1, ray.t_max, |t| {
+            let p = ray.at_t(t);
+            clipped_hit(p, azimuth(&p), 0.0, self.height, self.phi_max)
+        })?;
+
+        // Find parametric representation of cone hit.
+        let u = phi / self.phi_max;
+        let v = p_hit.z / self.height;
+
+        // Compute cone dpdu and dpdv.
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv = Vector3::new(
+            -1.0 * p_hit.x / (1.0 - v),
+            -1.0 * p_hit.y / (1.0 - v),
+            self.height,
+        );
+
+        // Compute cone dndu and dndv.
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = self.phi_max / (1.0 - v) * Vector3::new(p_hit.y, -1.0 * p_hit.x, 0.0);
+        let d2pdvv = Vector3::new(0.0, 0.0, 0.0);
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for cone intersection by evaluating the ray's
+        // parametric equation with error tracking.
+        let px = ox + t_shape_hit * dx;
+        let py = oy + t_shape_hit * dy;
+        let pz = oz + t_shape_hit * dz;
+        let p_error = Vector3::new(
+            px.absolute_error(),
+            py.absolute_error(),
+            pz.absolute_error(),
+        );
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((
+            f32::from(t_shape_hit),
+            self.object_to_world.transform(&interaction),
+        ))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.radius * (self.height * self.height + self.radius * self.radius).sqrt() * self.phi_max
+            / 2.0
+    }
+
+    /// Samples the cone uniformly by area. The circumference of the cone
+    /// shrinks linearly towards its apex, so v is sampled from a linear
+    /// density that falls to zero at the apex.
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let v = 1.0 - u.x.sqrt();
+        let phi = u.y * self.phi_max;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let hit_radius = (1.0 - v) * self.radius;
+        let p = Point3::new(hit_radius * cos_phi, hit_radius * sin_phi, v * self.height);
+
+        // The normal is dpdu x dpdv, with the lengths of the partial
+        // derivatives divided out so that it doesn't vanish at the apex.
+        let n = Vector3::new(self.height * cos_phi, self.height * sin_phi, self.radius);
+        let p_error = math::gamma(3) * p.to_vec().map(|f| f.abs());
+        let point = sampled_surface_point(self, p, p_error, n, Point2::new(u.y, v));
+        Some((point, 1.0 / self.surface_area()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Cone;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sample_area_is_uniform() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let cone = Cone::new(&object_to_world, false, 2.0, 1.0, 270.0);
+        assert_area_sampling(&cone, 0.75 * PI * 5.0f32.sqrt());
+    }
+
+    #[test]
+    fn sample_solid_angle_from_base() {
+        // Every direction above the center of the cone's base hits it once.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let cone = Cone::new(&object_to_world, false, 2.0, 1.0, 360.0);
+        assert_solid_angle_sampling(&cone, Point3::new(0.3, -0.2, 0.1), 2.0 * PI);
+    }
+}
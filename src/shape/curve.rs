@@ -0,0 +1,551 @@
+use super::Shape;
+use crate::bounding_box::Bounds3;
+use crate::interaction::SurfaceInteraction;
+use crate::math;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use crate::vector;
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3, Vector2, Vector3};
+
+/// The maximum number of times a curve segment is recursively split in half
+/// when it is intersected with a ray.
+const MAX_REFINEMENT_DEPTH: i32 = 10;
+
+/// Describes how a curve's surface is oriented and shaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveType {
+    /// A flat ribbon that always faces the incident ray.
+    Flat,
+
+    /// A flat ribbon that always faces the incident ray but whose shading
+    /// normal is rotated across its width so that it appears to be a
+    /// cylinder.
+    Cylinder,
+
+    /// A ribbon with a fixed orientation in space. The ribbon's surface
+    /// normal is interpolated between the given normals at the start and the
+    /// end of the curve.
+    Ribbon {
+        start_normal: Vector3<f32>,
+        end_normal: Vector3<f32>,
+    },
+}
+
+/// The data shared by all of the segments of a single cubic Bézier curve.
+/// Splitting a long curve into several segments gives each segment a tighter
+/// bounding box, and storing the control points once keeps the memory cost
+/// of the split small. (p. 168)
+pub struct CurveCommon<'a> {
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    curve_type: CurveType,
+
+    /// The curve's four Bézier control points in object space.
+    control_points: [Point3<f32>; 4],
+
+    /// The width of the curve at its start and end.
+    widths: (f32, f32),
+
+    /// The angle between a ribbon curve's start and end normals and the
+    /// inverse of its sine. These are used to spherically interpolate the
+    /// normal along the curve. Both are zero for other curve types.
+    normal_angle: f32,
+    inv_sin_normal_angle: f32,
+}
+
+impl<'a> CurveCommon<'a> {
+    /// Creates a cubic Bézier curve from its four object space control points
+    /// and the curve's width at its start and end.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        curve_type: CurveType,
+        control_points: [Point3<f32>; 4],
+        start_width: f32,
+        end_width: f32,
+    ) -> Self {
+        let (curve_type, normal_angle, inv_sin_normal_angle) = match curve_type {
+            CurveType::Ribbon {
+                start_normal,
+                end_normal,
+            } => {
+                let start_normal = start_normal.normalize();
+                let end_normal = end_normal.normalize();
+                let normal_angle = math::clamp(start_normal.dot(end_normal), 0.0, 1.0).acos();
+                let curve_type = CurveType::Ribbon {
+                    start_normal,
+                    end_normal,
+                };
+                (curve_type, normal_angle, 1.0 / normal_angle.sin())
+            }
+            curve_type => (curve_type, 0.0, 0.0),
+        };
+
+        Self {
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            curve_type,
+            control_points,
+            widths: (start_width, end_width),
+            normal_angle,
+            inv_sin_normal_angle,
+        }
+    }
+
+    /// Returns a reference to the segment of the curve between the parametric
+    /// values `u_min` and `u_max`.
+    pub fn segment(&'a self, u_min: f32, u_max: f32) -> Curve<'a> {
+        Curve {
+            common: self,
+            u_min,
+            u_max,
+        }
+    }
+
+    /// Returns an iterator over `segment_count` segments of equal parametric
+    /// length that together make up the whole curve.
+    pub fn segments(&'a self, segment_count: usize) -> impl Iterator<Item = Curve<'a>> {
+        (0..segment_count).map(move |i| {
+            let u_min = i as f32 / segment_count as f32;
+            let u_max = (i + 1) as f32 / segment_count as f32;
+            self.segment(u_min, u_max)
+        })
+    }
+
+    /// Returns the width of the curve at the parametric value `u`.
+    fn width_at(&self, u: f32) -> f32 {
+        math::lerp(u, self.widths.0, self.widths.1)
+    }
+
+    /// Returns the surface normal of a ribbon curve at the parametric value
+    /// `u`, spherically interpolated between the curve's start and end
+    /// normals. Returns `None` for other curve types.
+    fn ribbon_normal_at(&self, u: f32) -> Option<Vector3<f32>> {
+        match self.curve_type {
+            CurveType::Ribbon {
+                start_normal,
+                end_normal,
+            } => {
+                if self.normal_angle == 0.0 {
+                    return Some(start_normal);
+                }
+                let sin0 = ((1.0 - u) * self.normal_angle).sin() * self.inv_sin_normal_angle;
+                let sin1 = (u * self.normal_angle).sin() * self.inv_sin_normal_angle;
+                Some(sin0 * start_normal + sin1 * end_normal)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A reference to a segment of a cubic Bézier curve.
+pub struct Curve<'a> {
+    common: &'a CurveCommon<'a>,
+    u_min: f32,
+    u_max: f32,
+}
+
+/// The result of intersecting a ray with a curve segment in the recursive
+/// intersection test, with positions expressed in the ray's coordinate
+/// system.
+struct CurveHit {
+    t: f32,
+    u: f32,
+    v: f32,
+    hit_width: f32,
+    ribbon_normal: Option<Vector3<f32>>,
+}
+
+impl<'a> Curve<'a> {
+    /// Returns the object space control points of the Bézier curve that spans
+    /// just this segment of the full curve.
+    fn object_space_control_points(&self) -> [Point3<f32>; 4] {
+        let cp = &self.common.control_points;
+        let (u_min, u_max) = (self.u_min, self.u_max);
+        [
+            blossom_bezier(cp, u_min, u_min, u_min),
+            blossom_bezier(cp, u_min, u_min, u_max),
+            blossom_bezier(cp, u_min, u_max, u_max),
+            blossom_bezier(cp, u_max, u_max, u_max),
+        ]
+    }
+
+    /// Returns the largest width of the curve between the parametric values
+    /// `u0` and `u1`. Since the width varies linearly, it is largest at one of
+    /// the ends.
+    fn max_width(&self, u0: f32, u1: f32) -> f32 {
+        self.common.width_at(u0).max(self.common.width_at(u1))
+    }
+
+    /// Finds the nearest intersection between the ray and the curve segment
+    /// with control points `cp`, which are given in a coordinate system where
+    /// the ray starts at the origin and points down the +z axis. The segment
+    /// spans the parametric range [`u0`, `u1`] of the full curve. Only hits
+    /// whose distance along the ray is less than `z_max` are reported.
+    ///
+    /// The segment is split in half `depth` times, and sub-segments whose
+    /// bounds don't overlap the ray are culled. Once the segments are
+    /// approximately linear, each is intersected as a line segment with
+    /// width. (p. 173)
+    fn recursive_intersection(
+        &self,
+        ray: &Ray,
+        cp: &[Point3<f32>; 4],
+        u0: f32,
+        u1: f32,
+        depth: i32,
+        z_max: f32,
+    ) -> Option<CurveHit> {
+        let ray_length = ray.direction.magnitude();
+
+        if depth > 0 {
+            // Split curve segment into sub-segments and test for
+            // intersection.
+            let cp_split = subdivide_bezier(cp);
+            let u = [u0, 0.5 * (u0 + u1), u1];
+            let mut z_max = z_max;
+            let mut nearest_hit = None;
+            for segment in 0..2 {
+                let cps = [
+                    cp_split[3 * segment],
+                    cp_split[3 * segment + 1],
+                    cp_split[3 * segment + 2],
+                    cp_split[3 * segment + 3],
+                ];
+                let half_width = 0.5 * self.max_width(u[segment], u[segment + 1]);
+
+                // Cull the sub-segment if its bounds, expanded by half its
+                // width, don't overlap the ray. The ray lies along the z axis,
+                // so the sub-segment must straddle zero in x and y. Checking y
+                // first allows early exits most often.
+                let (y_min, y_max) = min_max(cps.iter().map(|p| p.y));
+                if y_max + half_width < 0.0 || y_min - half_width > 0.0 {
+                    continue;
+                }
+                let (x_min, x_max) = min_max(cps.iter().map(|p| p.x));
+                if x_max + half_width < 0.0 || x_min - half_width > 0.0 {
+                    continue;
+                }
+                let (z_min_cp, z_max_cp) = min_max(cps.iter().map(|p| p.z));
+                if z_max_cp + half_width < 0.0 || z_min_cp - half_width > z_max {
+                    continue;
+                }
+
+                if let Some(hit) = self.recursive_intersection(
+                    ray,
+                    &cps,
+                    u[segment],
+                    u[segment + 1],
+                    depth - 1,
+                    z_max,
+                ) {
+                    // Only hits nearer than this one are of interest in the
+                    // remaining sub-segment.
+                    z_max = hit.t * ray_length;
+                    nearest_hit = Some(hit);
+                }
+            }
+            nearest_hit
+        } else {
+            // Test the ray against the segment's endpoint boundaries, which
+            // are the lines through the endpoints perpendicular to the curve's
+            // tangents there.
+            let edge = (cp[1].y - cp[0].y) * -1.0 * cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+            if edge < 0.0 {
+                return None;
+            }
+            let edge = (cp[2].y - cp[3].y) * -1.0 * cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+            if edge < 0.0 {
+                return None;
+            }
+
+            // Compute the parameter w along the line between the segment's
+            // endpoints that gives the minimum distance to the ray.
+            let segment_direction = Vector2::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+            let denominator = segment_direction.magnitude2();
+            if denominator == 0.0 {
+                return None;
+            }
+            let w =
+                Vector2::new(-1.0 * cp[0].x, -1.0 * cp[0].y).dot(segment_direction) / denominator;
+
+            // Compute u coordinate of curve intersection point and the
+            // curve's width there.
+            let u = math::clamp(math::lerp(w, u0, u1), u0, u1);
+            let ribbon_normal = self.common.ribbon_normal_at(u);
+            let hit_width = match ribbon_normal {
+                // Scale the width based on the ribbon's orientation relative
+                // to the ray.
+                Some(n) => self.common.width_at(u) * n.dot(ray.direction).abs() / ray_length,
+                None => self.common.width_at(u),
+            };
+
+            // Test the intersection point against the curve's width.
+            let (pc, dpcdw) = eval_bezier(cp, math::clamp(w, 0.0, 1.0));
+            let pt_curve_dist2 = pc.x * pc.x + pc.y * pc.y;
+            if pt_curve_dist2 > hit_width * hit_width * 0.25 {
+                return None;
+            }
+            if pc.z < 0.0 || pc.z > z_max {
+                return None;
+            }
+
+            // Compute v coordinate of curve intersection point. v runs across
+            // the curve's width, with 0.5 along its center.
+            let pt_curve_dist = pt_curve_dist2.sqrt();
+            let edge_func = dpcdw.x * -1.0 * pc.y + pc.x * dpcdw.y;
+            let v = if edge_func > 0.0 {
+                0.5 + pt_curve_dist / hit_width
+            } else {
+                0.5 - pt_curve_dist / hit_width
+            };
+
+            Some(CurveHit {
+                t: pc.z / ray_length,
+                u,
+                v,
+                hit_width,
+                ribbon_normal,
+            })
+        }
+    }
+}
+
+impl<'a> Shape for Curve<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.common.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.common.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.common.reverse_orientation
+    }
+
+    fn object_bound(&self) -> Bounds3<f32> {
+        // A Bézier curve lies within the convex hull of its control points,
+        // so the control points' bounds, expanded by half the curve's width,
+        // bound the curve. (p. 170)
+        let cp = self.object_space_control_points();
+        let mut bounds =
+            Bounds3::from_corners(cp[0], cp[1]).union(&Bounds3::from_corners(cp[2], cp[3]));
+        bounds.expand(0.5 * self.max_width(self.u_min, self.u_max));
+        bounds
+    }
+
+    fn world_bound(&self) -> Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let ray = self.world_to_object().transform(ray);
+
+        // Project the curve's control points onto the plane perpendicular to
+        // the ray by transforming them into a coordinate system where the ray
+        // starts at the origin and points down the +z axis. The curve's
+        // overall direction is used as the up vector so that the projected
+        // curve is roughly aligned with the x axis. (p. 171)
+        let cp_obj = self.object_space_control_points();
+        let dx = ray.direction.cross(cp_obj[3] - cp_obj[0]);
+        let dx = if dx.magnitude2() == 0.0 {
+            vector::coordinate_system(ray.direction.normalize()).0
+        } else {
+            dx
+        };
+        let object_to_ray =
+            transform::Transform::look_at(ray.origin, ray.origin + ray.direction, dx)?;
+        let ray_to_object = object_to_ray.inverse();
+        let cp = [
+            object_to_ray.transform(&cp_obj[0]),
+            object_to_ray.transform(&cp_obj[1]),
+            object_to_ray.transform(&cp_obj[2]),
+            object_to_ray.transform(&cp_obj[3]),
+        ];
+
+        // Before refining the curve, check whether the ray's bounding box
+        // overlaps the curve's bounding box.
+        let z_max = ray.direction.magnitude() * ray.t_max;
+        let mut curve_bounds =
+            Bounds3::from_corners(cp[0], cp[1]).union(&Bounds3::from_corners(cp[2], cp[3]));
+        curve_bounds.expand(0.5 * self.max_width(self.u_min, self.u_max));
+        let ray_bounds =
+            Bounds3::from_corners(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, z_max));
+        if !curve_bounds.overlaps(&ray_bounds) {
+            return None;
+        }
+
+        // Compute refinement depth for curve. The curve is split until each
+        // sub-segment deviates from a straight line by less than a twentieth
+        // of the curve's width. (p. 172)
+        let l0 = (0..2)
+            .map(|i| {
+                let d = cp[i].to_vec() - 2.0 * cp[i + 1].to_vec() + cp[i + 2].to_vec();
+                d.x.abs().max(d.y.abs()).max(d.z.abs())
+            })
+            .fold(0.0f32, f32::max);
+        let eps = self.common.widths.0.max(self.common.widths.1) * 0.05;
+        // Compute log base 4 by dividing log base 2 in half.
+        let r0 = log2_int(std::f32::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)) / 2;
+        let max_depth = r0.clamp(0, MAX_REFINEMENT_DEPTH);
+
+        let hit =
+            self.recursive_intersection(&ray, &cp, self.u_min, self.u_max, max_depth, z_max)?;
+
+        // Compute dpdu and dpdv for curve intersection. dpdu follows the
+        // curve, and dpdv spans its width.
+        let (_, dpdu) = eval_bezier(&self.common.control_points, hit.u);
+        if dpdu.magnitude2() == 0.0 {
+            return None;
+        }
+        let dpdv = match hit.ribbon_normal {
+            Some(n) => n.cross(dpdu).normalize() * hit.hit_width,
+            None => {
+                // Flat and cylinder curves face the ray, so dpdv lies in the
+                // plane perpendicular to the ray.
+                let dpdu_plane = object_to_ray.transform(&dpdu);
+                let dpdv_plane = Vector3::new(-1.0 * dpdu_plane.y, dpdu_plane.x, 0.0).normalize()
+                    * hit.hit_width;
+                let dpdv_plane = if self.common.curve_type == CurveType::Cylinder {
+                    // Rotate dpdv around the curve's tangent so that the
+                    // curve's normal sweeps across half a cylinder.
+                    let theta = math::lerp(hit.v, -90.0, 90.0);
+                    transform::Transform::rotate(-1.0 * theta, dpdu_plane).transform(&dpdv_plane)
+                } else {
+                    dpdv_plane
+                };
+                ray_to_object.transform(&dpdv_plane)
+            }
+        };
+
+        // The intersection is approximate, so the error bound is
+        // conservatively set to the curve's width. (p. 176)
+        let p_error = Vector3::new(
+            2.0 * hit.hit_width,
+            2.0 * hit.hit_width,
+            2.0 * hit.hit_width,
+        );
+
+        let interaction = SurfaceInteraction::new(
+            ray.at_t(hit.t),
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(hit.u, hit.v),
+            dpdu,
+            dpdv,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+
+        Some((hit.t, self.object_to_world().transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        // Approximate the curve's length by the length of its control
+        // polygon, which is an upper bound. (p. 170)
+        let cp = self.object_space_control_points();
+        let approx_length: f32 = (0..3).map(|i| cp[i].distance(cp[i + 1])).sum();
+        let avg_width = 0.5 * (self.common.width_at(self.u_min) + self.common.width_at(self.u_max));
+        approx_length * avg_width
+    }
+}
+
+/// Returns the smallest and largest values in the iterator.
+fn min_max<I: Iterator<Item = f32>>(values: I) -> (f32, f32) {
+    values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+/// Returns the integer part of the base 2 logarithm of `v`, rounded to the
+/// nearest integer. Values less than one return zero.
+fn log2_int(v: f32) -> i32 {
+    if v < 1.0 {
+        return 0;
+    }
+    let bits = v.to_bits();
+    let exponent = (bits >> 23) as i32 - 127;
+    if bits & (1 << 22) != 0 {
+        exponent + 1
+    } else {
+        exponent
+    }
+}
+
+/// Evaluates the blossom of the cubic Bézier curve with control points `cp`
+/// at (`u0`, `u1`, `u2`). Blossoms with two or three equal arguments give the
+/// control points of a sub-segment of the curve. (p. 169)
+fn blossom_bezier(cp: &[Point3<f32>; 4], u0: f32, u1: f32, u2: f32) -> Point3<f32> {
+    let a = [
+        math::lerp_point(u0, cp[0], cp[1]),
+        math::lerp_point(u0, cp[1], cp[2]),
+        math::lerp_point(u0, cp[2], cp[3]),
+    ];
+    let b = [
+        math::lerp_point(u1, a[0], a[1]),
+        math::lerp_point(u1, a[1], a[2]),
+    ];
+    math::lerp_point(u2, b[0], b[1])
+}
+
+/// Splits the cubic Bézier curve with control points `cp` in half at its
+/// parametric midpoint. The first sub-curve's control points are the first
+/// four returned points, and the second's are the last four. (p. 173)
+fn subdivide_bezier(cp: &[Point3<f32>; 4]) -> [Point3<f32>; 7] {
+    [
+        cp[0],
+        midpoint(cp[0], cp[1]),
+        Point3::from_vec((cp[0].to_vec() + 2.0 * cp[1].to_vec() + cp[2].to_vec()) / 4.0),
+        Point3::from_vec(
+            (cp[0].to_vec() + 3.0 * cp[1].to_vec() + 3.0 * cp[2].to_vec() + cp[3].to_vec()) / 8.0,
+        ),
+        Point3::from_vec((cp[1].to_vec() + 2.0 * cp[2].to_vec() + cp[3].to_vec()) / 4.0),
+        midpoint(cp[2], cp[3]),
+        cp[3],
+    ]
+}
+
+/// Evaluates the cubic Bézier curve with control points `cp` at `u`, returning
+/// the point on the curve and the curve's derivative there. (p. 174)
+fn eval_bezier(cp: &[Point3<f32>; 4], u: f32) -> (Point3<f32>, Vector3<f32>) {
+    let cp1 = [
+        math::lerp_point(u, cp[0], cp[1]),
+        math::lerp_point(u, cp[1], cp[2]),
+        math::lerp_point(u, cp[2], cp[3]),
+    ];
+    let cp2 = [
+        math::lerp_point(u, cp1[0], cp1[1]),
+        math::lerp_point(u, cp1[1], cp1[2]),
+    ];
+    let derivative = if (cp2[1] - cp2[0]).magnitude2() > 0.0 {
+        3.0 * (cp2[1] - cp2[0])
+    } else {
+        // For a cubic Bézier, if the first three control points are coincident
+        // then the derivative of the curve is legitimately zero at u = 0. The
+        // difference between the first and last control points is returned
+        // instead so that a meaningful tangent is available.
+        cp[3] - cp[0]
+    };
+    (math::lerp_point(u, cp2[0], cp2[1]), derivative)
+}
+
+/// Returns the point halfway between `a` and `b`.
+fn midpoint(a: Point3<f32>, b: Point3<f32>) -> Point3<f32> {
+    Point3::from_vec(0.5 * (a.to_vec() + b.to_vec()))
+}
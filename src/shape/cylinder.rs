@@ -0,0 +1,208 @@
+use super::azimuth;
+use super::clipped_hit;
+use super::nearest_quadric_hit;
+use super::sampled_surface_point;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
+use crate::transform::SwapHandedness;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+use efloat::EFloat32;
+
+/// A cylinder centered on the z axis in object space.
+pub struct Cylinder<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Cylinder-specific fields
+    radius: f32,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32,
+}
+
+impl<'a> Cylinder<'a> {
+    /// Creates a cylinder that extends from `z_min` to `z_max` along the z axis
+    /// and is swept around the z axis up to `phi_max` degrees.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        radius: f32,
+        z_min: f32,
+        z_max: f32,
+        phi_max: f32,
+    ) -> Self {
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Cylinder-specific fields
+            radius,
+            z_min: z_min.min(z_max),
+            z_max: z_min.max(z_max),
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+        }
+    }
+
+    /// Returns the object space point on the cylinder's surface that is hit by
+    /// the ray at parametric value `t`.
+    fn hit_point(&self, ray: &Ray, t: f32) -> Point3<f32> {
+        let mut p = ray.at_t(t);
+
+        // Refine the cylinder intersection point by reprojecting it onto the
+        // surface.
+        let hit_radius = (p.x * p.x + p.y * p.y).sqrt();
+        p.x *= self.radius / hit_radius;
+        p.y *= self.radius / hit_radius;
+        p
+    }
+}
+
+impl<'a> Shape for Cylinder<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(-1.0 * self.radius, -1.0 * self.radius, self.z_min),
+            cgmath::Point3::new(self.radius, self.radius, self.z_max),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let (ray, (o_err, d_err)) = self.world_to_object().transform_with_error(ray);
+
+        // Initialize ray values.
+        let ox = EFloat32::new_with_err(ray.origin.x, o_err.x);
+        let oy = EFloat32::new_with_err(ray.origin.y, o_err.y);
+        let dx = EFloat32::new_with_err(ray.direction.x, d_err.x);
+        let dy = EFloat32::new_with_err(ray.direction.y, d_err.y);
+
+        // Compute quadratic cylinder coefficients.
+        let a = dx * dx + dy * dy;
+        let b = EFloat32::new(2.0) * (dx * ox + dy * oy);
+        let c = ox * ox + oy * oy - EFloat32::new(self.radius) * EFloat32::new(self.radius);
+
+        // Solve quadratic equation for t values and find the nearest hit that
+        // hasn't been clipped away.
+        let (t0, t1) = math::quadratic(a, b, c)?;
+        let (t_shape_hit, (p_hit, phi)) = nearest_quadric_hit(t0, t1, ray.t_max, |t| {
+            let p = self.hit_point(&ray, t);
+            clipped_hit(p, azimuth(&p), self.z_min, self.z_max, self.phi_max)
+        })?;
+
+        // Find parametric representation of cylinder hit.
+        let u = phi / self.phi_max;
+        let v = (p_hit.z - self.z_min) / (self.z_max - self.z_min);
+
+        // Compute cylinder dpdu and dpdv.
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv = Vector3::new(0.0, 0.0, self.z_max - self.z_min);
+
+        // Compute cylinder dndu and dndv.
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = Vector3::new(0.0, 0.0, 0.0);
+        let d2pdvv = Vector3::new(0.0, 0.0, 0.0);
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for cylinder intersection. Only the x and y
+        // dimensions were reprojected onto the surface. (p. 226)
+        let p_error = math::gamma(3) * Vector3::new(p_hit.x.abs(), p_hit.y.abs(), 0.0);
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((
+            f32::from(t_shape_hit),
+            self.object_to_world.transform(&interaction),
+        ))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        (self.z_max - self.z_min) * self.radius * self.phi_max
+    }
+
+    /// Samples the cylinder uniformly by area, which is uniform in both z and
+    /// phi. (p. 840)
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let z = math::lerp(u.x, self.z_min, self.z_max);
+        let phi = u.y * self.phi_max;
+        let p = Point3::new(self.radius * phi.cos(), self.radius * phi.sin(), z);
+        let n = Vector3::new(p.x, p.y, 0.0);
+        let p_error = math::gamma(3) * Vector3::new(p.x.abs(), p.y.abs(), 0.0);
+        let point = sampled_surface_point(self, p, p_error, n, Point2::new(u.y, u.x));
+        Some((point, 1.0 / self.surface_area()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Cylinder;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    #[test]
+    fn sample_area_is_uniform() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let cylinder = Cylinder::new(&object_to_world, false, 1.0, -1.0, 2.0, 300.0);
+        assert_area_sampling(&cylinder, 300.0f32.to_radians() * 3.0);
+    }
+
+    #[test]
+    fn sample_solid_angle_from_axis() {
+        // Every direction from a point on the axis hits the cylinder at most
+        // once.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let cylinder = Cylinder::new(&object_to_world, false, 1.0, -1.0, 2.0, 300.0);
+        let cos_theta = |z: f32| z / (z * z + 1.0).sqrt();
+        let expected = 300.0f32.to_radians() * (cos_theta(2.0) - cos_theta(-1.0));
+        assert_solid_angle_sampling(&cylinder, Point3::new(0.3, -0.2, 0.1), expected);
+    }
+}
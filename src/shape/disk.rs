@@ -0,0 +1,193 @@
+use super::azimuth;
+use super::sampled_surface_point;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+
+/// A disk or annulus perpendicular to the z axis and centered on it in object
+/// space.
+pub struct Disk<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Disk-specific fields
+    height: f32,
+    radius: f32,
+    inner_radius: f32,
+    phi_max: f32,
+}
+
+impl<'a> Disk<'a> {
+    /// Creates a disk at `height` along the z axis with a hole of radius
+    /// `inner_radius` in its center that is swept around the z axis up to
+    /// `phi_max` degrees.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        height: f32,
+        radius: f32,
+        inner_radius: f32,
+        phi_max: f32,
+    ) -> Self {
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Disk-specific fields
+            height,
+            radius,
+            inner_radius,
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+        }
+    }
+}
+
+impl<'a> Shape for Disk<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(-1.0 * self.radius, -1.0 * self.radius, self.height),
+            cgmath::Point3::new(self.radius, self.radius, self.height),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let ray = self.world_to_object().transform(ray);
+
+        // Compute plane intersection for disk. Rays parallel to the disk's
+        // plane never hit it.
+        if ray.direction.z == 0.0 {
+            return None;
+        }
+        let t_shape_hit = (self.height - ray.origin.z) / ray.direction.z;
+        if t_shape_hit <= 0.0 || t_shape_hit >= ray.t_max {
+            return None;
+        }
+
+        // See if the hit point is inside the disk radii and the maximum phi.
+        let mut p_hit = ray.at_t(t_shape_hit);
+        let dist2 = p_hit.x * p_hit.x + p_hit.y * p_hit.y;
+        if dist2 > self.radius * self.radius || dist2 < self.inner_radius * self.inner_radius {
+            return None;
+        }
+        let phi = azimuth(&p_hit);
+        if phi > self.phi_max {
+            return None;
+        }
+
+        // Find parametric representation of disk hit.
+        let u = phi / self.phi_max;
+        let hit_radius = dist2.sqrt();
+        let v = (self.radius - hit_radius) / (self.radius - self.inner_radius);
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv =
+            Vector3::new(p_hit.x, p_hit.y, 0.0) * (self.inner_radius - self.radius) / hit_radius;
+        let dndu = Vector3::new(0.0, 0.0, 0.0);
+        let dndv = Vector3::new(0.0, 0.0, 0.0);
+
+        // Refine disk intersection point. Since the hit point is projected
+        // exactly onto the disk's plane, it has no error. (p. 226)
+        p_hit.z = self.height;
+        let p_error = Vector3::new(0.0, 0.0, 0.0);
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((t_shape_hit, self.object_to_world.transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.phi_max * 0.5 * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+    }
+
+    /// Samples the disk uniformly by area. The squared radius of the sampled
+    /// point is uniform between the squared inner and outer radii. (p. 839)
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let radius2 = math::lerp(
+            u.x,
+            self.inner_radius * self.inner_radius,
+            self.radius * self.radius,
+        );
+        let hit_radius = radius2.sqrt();
+        let phi = u.y * self.phi_max;
+        let p = Point3::new(hit_radius * phi.cos(), hit_radius * phi.sin(), self.height);
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let v = (self.radius - hit_radius) / (self.radius - self.inner_radius);
+        let point =
+            sampled_surface_point(self, p, Vector3::new(0.0, 0.0, 0.0), n, Point2::new(u.y, v));
+        Some((point, 1.0 / self.surface_area()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Disk;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sample_area_is_uniform() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let disk = Disk::new(&object_to_world, false, 0.5, 1.5, 0.5, 270.0);
+        assert_area_sampling(&disk, 0.75 * PI * (1.5 * 1.5 - 0.5 * 0.5));
+    }
+
+    #[test]
+    fn sample_solid_angle_from_axis() {
+        // The reference point is on the disk's axis, one unit above it.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let disk = Disk::new(&object_to_world, false, 0.5, 1.5, 0.5, 360.0);
+        let cos_theta = |radius: f32| 1.0 / (1.0 + radius * radius).sqrt();
+        let expected = 2.0 * PI * (cos_theta(0.5) - cos_theta(1.5));
+        assert_solid_angle_sampling(&disk, Point3::new(0.3, -0.2, 1.6), expected);
+    }
+}
@@ -0,0 +1,353 @@
+use super::edge_key;
+use crate::texture::Texture;
+use crate::texture::TextureEvalContext;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::vector;
+use crate::vector::Normal3;
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point2, Point3, Vector3};
+use std::collections::HashMap;
+
+/// The number of times an edge of the input mesh may be halved during
+/// tessellation. This bounds the size of the tessellated mesh when the
+/// tessellation criterion can't be met, such as when the camera lies on the
+/// surface.
+const MAX_EDGE_LEVEL: u32 = 12;
+
+/// Decides whether an edge is too long to be displaced without losing detail.
+#[derive(Clone, Copy, Debug)]
+pub enum TessellationCriterion {
+    /// Split edges that are longer than the given length in world space.
+    EdgeLength(f32),
+
+    /// Split edges that subtend an angle larger than `max_angle` radians as
+    /// seen from `camera_position`. Setting `max_angle` to the angle that a
+    /// pixel subtends tessellates the surface to roughly one vertex per pixel.
+    ScreenSpace {
+        camera_position: Point3<f32>,
+        max_angle: f32,
+    },
+}
+
+impl TessellationCriterion {
+    /// Returns a flag that indicates whether the edge between the world space
+    /// points `p0` and `p1` should be split.
+    fn should_split(&self, p0: Point3<f32>, p1: Point3<f32>) -> bool {
+        let length = p0.distance(p1);
+        match *self {
+            TessellationCriterion::EdgeLength(max_length) => length > max_length,
+            TessellationCriterion::ScreenSpace {
+                camera_position,
+                max_angle,
+            } => {
+                let distance = camera_position.distance(p0.midpoint(p1));
+                length > max_angle * distance
+            }
+        }
+    }
+}
+
+/// A displacement stage for a `TriangleMesh`. The mesh's triangles are
+/// tessellated until every edge meets the tessellation criterion, and then
+/// each vertex is moved along the surface normal by the distance given by a
+/// scalar texture.
+///
+/// Whether an edge is split depends only on the edge itself, so triangles
+/// that share an edge split it the same way and the displaced surface has no
+/// cracks.
+pub struct Displacement<'a> {
+    texture: &'a dyn Texture<f32>,
+    criterion: TessellationCriterion,
+}
+
+impl<'a> Displacement<'a> {
+    /// Creates a displacement stage that moves vertices by the value of
+    /// `texture`, in object space units, after tessellating the mesh according
+    /// to `criterion`.
+    pub fn new(texture: &'a dyn Texture<f32>, criterion: TessellationCriterion) -> Self {
+        Self { texture, criterion }
+    }
+
+    /// Tessellates and displaces the object space mesh.
+    ///
+    /// The displacement direction at each vertex is the mesh's interpolated
+    /// normal, or the area-weighted average of the normals of the faces
+    /// around the vertex if the mesh has no normals. The returned mesh's
+    /// normals are recomputed from its displaced faces.
+    pub(super) fn apply(
+        &self,
+        object_to_world: &transform::Transform,
+        mesh: DisplacedMesh,
+    ) -> DisplacedMesh {
+        let normals = match &mesh.normals {
+            Some(normals) => normals.clone(),
+            None => face_averaged_normals(&mesh.vertices, &mesh.triangle_vertex_indices),
+        };
+        let mut tessellator = Tessellator {
+            criterion: self.criterion,
+            world_space_vertices: mesh
+                .vertices
+                .iter()
+                .map(|p| object_to_world.transform(p))
+                .collect(),
+            vertices: mesh.vertices,
+            normals,
+            tangents: mesh.tangents,
+            uvs: mesh.uvs,
+            edges: HashMap::new(),
+            triangles: vec![],
+        };
+        for &(i0, i1, i2) in mesh.triangle_vertex_indices.iter() {
+            tessellator.tessellate([i0, i1, i2]);
+        }
+
+        // Move each vertex along its normal by the texture's value there.
+        let mut vertices = tessellator.vertices;
+        for (i, p) in vertices.iter_mut().enumerate() {
+            let n = tessellator.normals[i];
+            let context = TextureEvalContext {
+                point: tessellator.world_space_vertices[i],
+                normal: object_to_world.transform(&Normal3(n)).0.normalize(),
+                uv: match &tessellator.uvs {
+                    Some(uvs) => uvs[i],
+                    None => Point2::new(0.0, 0.0),
+                },
+            };
+            *p += n * self.texture.evaluate(&context);
+        }
+
+        // Recompute the normals from the displaced faces, keeping them on the
+        // same side of the surface as the normals they were displaced along.
+        let triangle_vertex_indices: Vec<_> = tessellator
+            .triangles
+            .iter()
+            .map(|t| (t[0], t[1], t[2]))
+            .collect();
+        let normals = face_averaged_normals(&vertices, &triangle_vertex_indices)
+            .into_iter()
+            .zip(tessellator.normals.iter())
+            .map(|(n, &n_displaced_along)| {
+                if n.magnitude2() > 0.0 {
+                    vector::face_forward(n, n_displaced_along)
+                } else {
+                    n_displaced_along
+                }
+            })
+            .collect();
+
+        DisplacedMesh {
+            vertices,
+            triangle_vertex_indices,
+            normals: Some(normals),
+            tangents: tessellator.tangents,
+            uvs: tessellator.uvs,
+        }
+    }
+}
+
+/// The object space vertices and attributes of a triangle mesh before or
+/// after displacement.
+pub(super) struct DisplacedMesh {
+    pub(super) vertices: Vec<Point3<f32>>,
+    pub(super) triangle_vertex_indices: Vec<(usize, usize, usize)>,
+    pub(super) normals: Option<Vec<Vector3<f32>>>,
+    pub(super) tangents: Option<Vec<Vector3<f32>>>,
+    pub(super) uvs: Option<Vec<Point2<f32>>>,
+}
+
+/// An edge of the tessellated mesh, keyed by the indices of its endpoints.
+struct Edge {
+    /// The number of times the input mesh's edges were halved or split across
+    /// to produce this edge.
+    level: u32,
+
+    /// The vertex at the edge's midpoint, if the edge has been split.
+    midpoint: Option<usize>,
+}
+
+/// Splits the triangles of a mesh, with the vertex attributes interpolated to
+/// each new vertex, until their edges meet the tessellation criterion.
+struct Tessellator {
+    criterion: TessellationCriterion,
+    vertices: Vec<Point3<f32>>,
+    world_space_vertices: Vec<Point3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    tangents: Option<Vec<Vector3<f32>>>,
+    uvs: Option<Vec<Point2<f32>>>,
+    edges: HashMap<(usize, usize), Edge>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl Tessellator {
+    /// Splits the triangle with the given vertices until its edges meet the
+    /// tessellation criterion, and adds the resulting triangles to the mesh.
+    ///
+    /// The split edges' midpoints are connected to form the new triangles, so
+    /// the triangle is split into two, three or four triangles depending on
+    /// how many of its edges are split. The winding order of the vertices is
+    /// preserved.
+    fn tessellate(&mut self, triangle: [usize; 3]) {
+        let mut stack = vec![triangle];
+        while let Some(v) = stack.pop() {
+            let split = [
+                self.should_split(v[0], v[1]),
+                self.should_split(v[1], v[2]),
+                self.should_split(v[2], v[0]),
+            ];
+
+            // Record the triangle's edges, so that the edges of the input mesh
+            // keep their level when the triangle's children are recorded.
+            for i in 0..3 {
+                self.edges
+                    .entry(edge_key(v[i], v[(i + 1) % 3]))
+                    .or_insert(Edge {
+                        level: 0,
+                        midpoint: None,
+                    });
+            }
+            let level = 1
+                + (0..3)
+                    .map(|i| self.edge_level(v[i], v[(i + 1) % 3]))
+                    .max()
+                    .unwrap_or(0);
+
+            // Rotate the vertices so that the first edge is split and, when
+            // two edges are split, the last edge is the one that isn't.
+            let split_count = split.iter().filter(|&&s| s).count();
+            let first = match split_count {
+                0 => {
+                    self.triangles.push(v);
+                    continue;
+                }
+                2 => (0..3)
+                    .find(|&i| split[i] && split[(i + 1) % 3])
+                    .unwrap_or(0),
+                _ => (0..3).find(|&i| split[i]).unwrap_or(0),
+            };
+            let (a, b, c) = (v[first], v[(first + 1) % 3], v[(first + 2) % 3]);
+
+            let children = match split_count {
+                1 => {
+                    let m_ab = self.midpoint(a, b);
+                    vec![[a, m_ab, c], [m_ab, b, c]]
+                }
+                2 => {
+                    let m_ab = self.midpoint(a, b);
+                    let m_bc = self.midpoint(b, c);
+                    vec![[m_ab, b, m_bc], [a, m_ab, m_bc], [a, m_bc, c]]
+                }
+                _ => {
+                    let m_ab = self.midpoint(a, b);
+                    let m_bc = self.midpoint(b, c);
+                    let m_ca = self.midpoint(c, a);
+                    vec![
+                        [a, m_ab, m_ca],
+                        [m_ab, b, m_bc],
+                        [m_ca, m_bc, c],
+                        [m_ab, m_bc, m_ca],
+                    ]
+                }
+            };
+
+            // The edges inside the triangle are only shared by its children,
+            // so their level can be set from the triangle's edges.
+            for child in children.iter() {
+                for i in 0..3 {
+                    self.edges
+                        .entry(edge_key(child[i], child[(i + 1) % 3]))
+                        .or_insert(Edge {
+                            level,
+                            midpoint: None,
+                        });
+                }
+            }
+            stack.extend(children);
+        }
+    }
+
+    fn edge_level(&self, a: usize, b: usize) -> u32 {
+        self.edges.get(&edge_key(a, b)).map_or(0, |edge| edge.level)
+    }
+
+    fn should_split(&self, a: usize, b: usize) -> bool {
+        self.edge_level(a, b) < MAX_EDGE_LEVEL
+            && self
+                .criterion
+                .should_split(self.world_space_vertices[a], self.world_space_vertices[b])
+    }
+
+    /// Returns the vertex at the midpoint of the edge between `a` and `b`,
+    /// creating it if the edge hasn't been split yet. The edge's halves are
+    /// one level deeper than the edge.
+    fn midpoint(&mut self, a: usize, b: usize) -> usize {
+        let key = edge_key(a, b);
+        if let Some(m) = self.edges.get(&key).and_then(|edge| edge.midpoint) {
+            return m;
+        }
+
+        let m = self.vertices.len();
+        self.vertices
+            .push(self.vertices[a].midpoint(self.vertices[b]));
+        self.world_space_vertices
+            .push(self.world_space_vertices[a].midpoint(self.world_space_vertices[b]));
+        let n = self.normals[a] + self.normals[b];
+        self.normals.push(if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            self.normals[a]
+        });
+        if let Some(tangents) = &mut self.tangents {
+            tangents.push((tangents[a] + tangents[b]) * 0.5);
+        }
+        if let Some(uvs) = &mut self.uvs {
+            uvs.push(uvs[a].midpoint(uvs[b]));
+        }
+
+        let level = self.edge_level(a, b);
+        self.edges.insert(
+            key,
+            Edge {
+                level,
+                midpoint: Some(m),
+            },
+        );
+        for &half in [(a, m), (m, b)].iter() {
+            self.edges.insert(
+                edge_key(half.0, half.1),
+                Edge {
+                    level: level + 1,
+                    midpoint: None,
+                },
+            );
+        }
+        m
+    }
+}
+
+/// Returns the normal at each vertex as the area-weighted average of the
+/// normals of the faces around it. The normals of vertices that aren't part
+/// of a non-degenerate face are zero.
+fn face_averaged_normals(
+    vertices: &[Point3<f32>],
+    triangle_vertex_indices: &[(usize, usize, usize)],
+) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    for &(i0, i1, i2) in triangle_vertex_indices.iter() {
+        // The cross product's length is twice the triangle's area, so adding
+        // it weights each face's normal by its area.
+        let n = (vertices[i1] - vertices[i0]).cross(vertices[i2] - vertices[i0]);
+        normals[i0] += n;
+        normals[i1] += n;
+        normals[i2] += n;
+    }
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.magnitude2() > 0.0 {
+                n.normalize()
+            } else {
+                n
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,385 @@
+use super::bilinear_patch::bilerp;
+use super::bilinear_patch::bilerp_edge_u;
+use super::bilinear_patch::bilerp_edge_v;
+use super::bilinear_patch::bilinear_intersection;
+use super::bilinear_patch::bilinear_point_and_derivatives;
+use super::bilinear_patch::corner_abs_sum;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box::Bounds2;
+use crate::bounding_box::Bounds3;
+use crate::interaction::SurfaceInteraction;
+use crate::math;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use crate::vector;
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+/// A height field over the unit square in object space. Heights are sampled
+/// on a regular grid of points that spans x and y from 0 to 1, and the surface
+/// over each grid cell is the bilinear interpolation of the heights at the
+/// cell's corners.
+///
+/// Rays are intersected by marching through the grid cells that the ray
+/// passes over, so only the heights themselves are stored. A cell's bounds are
+/// given by its extent in x and y and the range of its corner heights, and
+/// cells that the ray passes above or below are skipped without being
+/// intersected.
+pub struct Heightfield<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Heightfield-specific fields
+    /// The number of height samples along the x and y axes.
+    x_samples: usize,
+    y_samples: usize,
+
+    /// The height samples in row-major order, with x varying fastest.
+    heights: Vec<f32>,
+
+    z_min: f32,
+    z_max: f32,
+}
+
+/// An error that occurs when a `Heightfield` is created from inconsistent
+/// data.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeightfieldError {
+    /// There are fewer than two samples along an axis, so the grid has no
+    /// cells.
+    TooFewSamples { x_samples: usize, y_samples: usize },
+
+    /// The number of heights doesn't match the dimensions of the grid.
+    SampleCountMismatch {
+        height_count: usize,
+        x_samples: usize,
+        y_samples: usize,
+    },
+}
+
+impl std::fmt::Display for HeightfieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeightfieldError::TooFewSamples {
+                x_samples,
+                y_samples,
+            } => write!(
+                f,
+                "heightfield needs at least 2x2 samples but has {}x{}",
+                x_samples, y_samples
+            ),
+            HeightfieldError::SampleCountMismatch {
+                height_count,
+                x_samples,
+                y_samples,
+            } => write!(
+                f,
+                "heightfield has {} heights but a {}x{} grid",
+                height_count, x_samples, y_samples
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeightfieldError {}
+
+impl<'a> Heightfield<'a> {
+    /// Creates a heightfield from a grid of `x_samples` by `y_samples` heights
+    /// given in row-major order.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        x_samples: usize,
+        y_samples: usize,
+        heights: Vec<f32>,
+    ) -> Result<Self, HeightfieldError> {
+        if x_samples < 2 || y_samples < 2 {
+            return Err(HeightfieldError::TooFewSamples {
+                x_samples,
+                y_samples,
+            });
+        }
+        if heights.len() != x_samples * y_samples {
+            return Err(HeightfieldError::SampleCountMismatch {
+                height_count: heights.len(),
+                x_samples,
+                y_samples,
+            });
+        }
+
+        let z_min = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let z_max = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        Ok(Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Heightfield-specific fields
+            x_samples,
+            y_samples,
+            heights,
+            z_min,
+            z_max,
+        })
+    }
+
+    /// Returns the height sample at grid point (`x`, `y`).
+    fn height(&self, x: usize, y: usize) -> f32 {
+        self.heights[y * self.x_samples + x]
+    }
+
+    /// Returns the object space point at grid point (`x`, `y`).
+    fn grid_point(&self, x: usize, y: usize) -> Point3<f32> {
+        Point3::new(
+            x as f32 / (self.x_samples - 1) as f32,
+            y as f32 / (self.y_samples - 1) as f32,
+            self.height(x, y),
+        )
+    }
+
+    /// Returns the extent in x and y of the grid cell (`x`, `y`).
+    fn cell_extent(&self, x: usize, y: usize) -> Bounds2<f32> {
+        let p_min = self.grid_point(x, y);
+        let p_max = self.grid_point(x + 1, y + 1);
+        Bounds2::from_corners(Point2::new(p_min.x, p_min.y), Point2::new(p_max.x, p_max.y))
+    }
+
+    /// Returns the object space bounds of the surface over grid cell (`x`,
+    /// `y`). A bilinear patch lies within the convex hull of its corners, so
+    /// the cell's extent and the range of its corner heights bound it.
+    fn cell_bounds(&self, x: usize, y: usize) -> Bounds3<f32> {
+        let extent = self.cell_extent(x, y);
+        let heights = [
+            self.height(x, y),
+            self.height(x + 1, y),
+            self.height(x, y + 1),
+            self.height(x + 1, y + 1),
+        ];
+        let z_min = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let z_max = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        Bounds3::from_corners(
+            Point3::new(extent.min().x, extent.min().y, z_min),
+            Point3::new(extent.max().x, extent.max().y, z_max),
+        )
+    }
+
+    /// Returns the corners of grid cell (`x`, `y`) in the order p00, p10, p01,
+    /// p11.
+    fn cell_corners(&self, x: usize, y: usize) -> [Point3<f32>; 4] {
+        [
+            self.grid_point(x, y),
+            self.grid_point(x + 1, y),
+            self.grid_point(x, y + 1),
+            self.grid_point(x + 1, y + 1),
+        ]
+    }
+
+    /// Returns the surface normal at grid point (`x`, `y`), estimated from the
+    /// neighboring heights with central differences. One-sided differences
+    /// are used on the edges of the grid.
+    fn vertex_normal(&self, x: usize, y: usize) -> Vector3<f32> {
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.x_samples - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(self.y_samples - 1));
+        let dzdx = (self.height(x1, y) - self.height(x0, y))
+            / ((x1 - x0) as f32 / (self.x_samples - 1) as f32);
+        let dzdy = (self.height(x, y1) - self.height(x, y0))
+            / ((y1 - y0) as f32 / (self.y_samples - 1) as f32);
+        Vector3::new(-1.0 * dzdx, -1.0 * dzdy, 1.0).normalize()
+    }
+
+    /// Returns the first grid cell, in the order that the object space ray
+    /// passes over them, whose surface the ray intersects, along with the
+    /// parametric t value and the cell's (u, v) coordinates of the hit.
+    fn march_cells(&self, ray: &Ray) -> Option<(usize, usize, f32, Point2<f32>)> {
+        let (t_enter, t_exit) = self.object_bound().ray_intersection(ray)?;
+
+        // Set up the walk through the grid cells from the point where the ray
+        // enters the heightfield's bounds.
+        let cell_counts = [self.x_samples - 1, self.y_samples - 1];
+        let p_enter = ray.at_t(t_enter);
+        let mut cell = [0usize; 2];
+        let mut next_crossing_t = [f32::INFINITY; 2];
+        let mut delta_t = [f32::INFINITY; 2];
+        let mut step_forward = [true; 2];
+        for axis in 0..2 {
+            let count = cell_counts[axis];
+            let cell_index = (p_enter[axis] * count as f32).floor() as isize;
+            cell[axis] = cell_index.max(0).min(count as isize - 1) as usize;
+            let d = ray.direction[axis];
+            if d > 0.0 {
+                let boundary = (cell[axis] + 1) as f32 / count as f32;
+                next_crossing_t[axis] = t_enter + (boundary - p_enter[axis]) / d;
+                delta_t[axis] = 1.0 / (count as f32 * d);
+            } else if d < 0.0 {
+                let boundary = cell[axis] as f32 / count as f32;
+                next_crossing_t[axis] = t_enter + (boundary - p_enter[axis]) / d;
+                delta_t[axis] = -1.0 / (count as f32 * d);
+                step_forward[axis] = false;
+            }
+        }
+
+        let mut t_cell_enter = t_enter;
+        loop {
+            let t_cell_exit = next_crossing_t[0].min(next_crossing_t[1]).min(t_exit);
+
+            // Skip the cell if the ray passes entirely above or below it while
+            // it is over the cell.
+            let bounds = self.cell_bounds(cell[0], cell[1]);
+            let z0 = ray.at_t(t_cell_enter).z;
+            let z1 = ray.at_t(t_cell_exit).z;
+            if z0.max(z1) >= bounds.min().z && z0.min(z1) <= bounds.max().z {
+                let [p00, p10, p01, p11] = self.cell_corners(cell[0], cell[1]);
+                if let Some((t, uv)) = bilinear_intersection(ray, p00, p10, p01, p11) {
+                    return Some((cell[0], cell[1], t, uv));
+                }
+            }
+
+            // Advance to the next cell along the axis whose cell boundary the
+            // ray crosses first.
+            let axis = if next_crossing_t[0] < next_crossing_t[1] {
+                0
+            } else {
+                1
+            };
+            if next_crossing_t[axis] > t_exit {
+                return None;
+            }
+            if step_forward[axis] {
+                if cell[axis] + 1 == cell_counts[axis] {
+                    return None;
+                }
+                cell[axis] += 1;
+            } else {
+                if cell[axis] == 0 {
+                    return None;
+                }
+                cell[axis] -= 1;
+            }
+            t_cell_enter = next_crossing_t[axis];
+            next_crossing_t[axis] += delta_t[axis];
+        }
+    }
+}
+
+impl<'a> Shape for Heightfield<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> Bounds3<f32> {
+        Bounds3::from_corners(
+            Point3::new(0.0, 0.0, self.z_min),
+            Point3::new(1.0, 1.0, self.z_max),
+        )
+    }
+
+    fn world_bound(&self) -> Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let ray = self.world_to_object().transform(ray);
+        let (x, y, t_shape_hit, cell_uv) = self.march_cells(&ray)?;
+        let [p00, p10, p01, p11] = self.cell_corners(x, y);
+
+        // Compute the hit point and the partial derivatives with respect to
+        // the heightfield's (u, v) coordinates, which span the whole grid,
+        // from the partial derivatives with respect to the cell's.
+        let (p_hit, dpdu, dpdv) = bilinear_point_and_derivatives(cell_uv, p00, p10, p01, p11);
+        let u_scale = (self.x_samples - 1) as f32;
+        let v_scale = (self.y_samples - 1) as f32;
+        let dpdu = dpdu * u_scale;
+        let dpdv = dpdv * v_scale;
+        let uv = Point2::new(
+            (x as f32 + cell_uv.x) / u_scale,
+            (y as f32 + cell_uv.y) / v_scale,
+        );
+
+        // Compute heightfield dndu and dndv.
+        let d2pduu = Vector3::new(0.0, 0.0, 0.0);
+        let d2pdvv = Vector3::new(0.0, 0.0, 0.0);
+        let d2pduv = ((p00 - p01) + (p11 - p10)) * u_scale * v_scale;
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for heightfield intersection.
+        let p_error = math::gamma(6) * corner_abs_sum(p00, p10, p01, p11);
+
+        let mut interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            uv,
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        // Initialize shading geometry from the normals at the cell's corners
+        // so that the surface appears smooth across cells. The geometric
+        // normal stays authoritative, so the shading normal is flipped to
+        // match it when the orientation is reversed.
+        let (n00, n10, n01, n11) = (
+            self.vertex_normal(x, y),
+            self.vertex_normal(x + 1, y),
+            self.vertex_normal(x, y + 1),
+            self.vertex_normal(x + 1, y + 1),
+        );
+        let ns = bilerp(cell_uv, n00, n10, n01, n11);
+        if ns.magnitude2() > 0.0 {
+            let ns = ns.normalize();
+            let dndu = bilerp_edge_u(cell_uv.y, n00, n10, n01, n11) * u_scale;
+            let dndv = bilerp_edge_v(cell_uv.x, n00, n10, n01, n11) * v_scale;
+
+            // Build a shading tangent frame around the shading normal, as is
+            // done for triangles.
+            let ss = dpdu.normalize();
+            let ts = ss.cross(ns);
+            let (ss, ts) = if ts.magnitude2() > 0.0 {
+                let ts = ts.normalize();
+                (ts.cross(ns), ts)
+            } else {
+                vector::coordinate_system(ns)
+            };
+            interaction.set_shading_geometry(ss, ts, dndu, dndv, false);
+        }
+
+        Some((t_shape_hit, self.object_to_world.transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, _test_alpha_texture: bool) -> bool {
+        let ray = self.world_to_object().transform(ray);
+        self.march_cells(&ray).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        // Approximate each cell's area by the area of the quadrilateral
+        // spanned by its diagonals, which is exact for planar cells.
+        let mut area = 0.0;
+        for y in 0..self.y_samples - 1 {
+            for x in 0..self.x_samples - 1 {
+                let [p00, p10, p01, p11] = self.cell_corners(x, y);
+                area += 0.5 * (p11 - p00).cross(p10 - p01).magnitude();
+            }
+        }
+        area
+    }
+}
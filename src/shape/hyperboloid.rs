@@ -0,0 +1,456 @@
+use super::clipped_hit;
+use super::nearest_quadric_hit;
+use super::sampled_surface_point;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
+use crate::transform::SwapHandedness;
+use cgmath::EuclideanSpace;
+use cgmath::InnerSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+use efloat::EFloat32;
+
+/// The number of intervals used to numerically integrate the hyperboloid's
+/// surface area.
+const AREA_INTEGRATION_INTERVALS: usize = 64;
+
+/// The largest number of steps taken to invert the integral of the
+/// hyperboloid's area element when sampling its surface.
+const MAX_AREA_INVERSION_STEPS: usize = 32;
+
+/// The largest number of points along the swept line segment that are tried
+/// when solving for the coefficients of the hyperboloid's implicit equation.
+const MAX_COEFFICIENT_STEPS: usize = 16;
+
+/// A hyperboloid of one sheet centered on the z axis in object space. The
+/// surface is formed by sweeping the line segment between two points around
+/// the z axis.
+pub struct Hyperboloid<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Hyperboloid-specific fields
+    p1: Point3<f32>,
+    p2: Point3<f32>,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32,
+    r_max: f32,
+
+    /// The coefficients of the hyperboloid's implicit equation,
+    /// a*x^2 + a*y^2 - c*z^2 = 1.
+    ah: f32,
+    ch: f32,
+
+    /// The hyperboloid's surface area, which is integrated numerically once
+    /// when the hyperboloid is created.
+    area: f32,
+}
+
+/// An error that occurs when a `Hyperboloid` is created from a line segment
+/// that doesn't sweep out a hyperboloid.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HyperboloidError {
+    /// The end points of the line segment coincide.
+    CoincidentPoints,
+
+    /// Both end points of the line segment lie at the same height, so the
+    /// segment sweeps out an annulus rather than a hyperboloid.
+    HorizontalSegment,
+
+    /// The coefficients of the hyperboloid's implicit equation couldn't be
+    /// solved for from the line segment.
+    UnsolvableCoefficients,
+}
+
+impl std::fmt::Display for HyperboloidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HyperboloidError::CoincidentPoints => {
+                write!(f, "hyperboloid segment has coincident end points")
+            }
+            HyperboloidError::HorizontalSegment => {
+                write!(f, "hyperboloid segment has end points at the same height")
+            }
+            HyperboloidError::UnsolvableCoefficients => write!(
+                f,
+                "hyperboloid coefficients can't be solved for from the segment"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HyperboloidError {}
+
+impl<'a> Hyperboloid<'a> {
+    /// Creates a hyperboloid by sweeping the line segment from `p1` to `p2`
+    /// around the z axis up to `phi_max` degrees.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        p1: Point3<f32>,
+        p2: Point3<f32>,
+        phi_max: f32,
+    ) -> Result<Self, HyperboloidError> {
+        if p1 == p2 {
+            return Err(HyperboloidError::CoincidentPoints);
+        }
+        if p1.z == p2.z {
+            return Err(HyperboloidError::HorizontalSegment);
+        }
+
+        let radius1 = (p1.x * p1.x + p1.y * p1.y).sqrt();
+        let radius2 = (p2.x * p2.x + p2.y * p2.y).sqrt();
+
+        // Compute implicit function coefficients for hyperboloid. The second
+        // point must not lie on the z = 0 plane for the coefficients to be
+        // solvable. (p. 148)
+        let (p1, p2) = if p2.z == 0.0 { (p2, p1) } else { (p1, p2) };
+        let mut pp = p1;
+        let (ah, ch) = (0..MAX_COEFFICIENT_STEPS)
+            .find_map(|_| {
+                pp += 2.0 * (p2 - p1);
+                let xy1 = pp.x * pp.x + pp.y * pp.y;
+                let xy2 = p2.x * p2.x + p2.y * p2.y;
+                let ah = (1.0 / xy1 - (pp.z * pp.z) / (xy1 * p2.z * p2.z))
+                    / (1.0 - (xy2 * pp.z * pp.z) / (xy1 * p2.z * p2.z));
+                let ch = (ah * xy2 - 1.0) / (p2.z * p2.z);
+                if ah.is_finite() && ch.is_finite() {
+                    Some((ah, ch))
+                } else {
+                    None
+                }
+            })
+            .ok_or(HyperboloidError::UnsolvableCoefficients)?;
+
+        let mut hyperboloid = Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Hyperboloid-specific fields
+            p1,
+            p2,
+            z_min: p1.z.min(p2.z),
+            z_max: p1.z.max(p2.z),
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+            r_max: radius1.max(radius2),
+            ah,
+            ch,
+            area: 0.0,
+        };
+        hyperboloid.area = hyperboloid.phi_max * hyperboloid.integrate_area_element(0.0, 1.0);
+        Ok(hyperboloid)
+    }
+
+    /// Returns the azimuth of the object space point `p` on the hyperboloid's
+    /// surface, in the range [0, 2pi). The azimuth is measured from the point
+    /// on the swept line segment at the same height.
+    fn segment_azimuth(&self, p: &Point3<f32>) -> f32 {
+        let v = (p.z - self.p1.z) / (self.p2.z - self.p1.z);
+        let pr = self.p1 + v * (self.p2 - self.p1);
+        let phi = (pr.x * p.y - p.x * pr.y).atan2(p.x * pr.x + p.y * pr.y);
+        if phi < 0.0 {
+            phi + 2.0 * std::f32::consts::PI
+        } else {
+            phi
+        }
+    }
+
+    /// Returns the hyperboloid's area element at height `v` along the swept
+    /// segment, per unit of v and of phi. This is |t(v) x d|, where d is the
+    /// segment's direction and t(v) is the tangent of the circle swept by the
+    /// segment's point at v.
+    fn area_element(&self, v: f32) -> f32 {
+        let pr = self.p1.to_vec() + v * (self.p2 - self.p1);
+        let tangent = Vector3::new(-1.0 * pr.y, pr.x, 0.0);
+        tangent.cross(self.p2 - self.p1).magnitude()
+    }
+
+    /// Integrates the area element over the range [`v0`, `v1`] with Simpson's
+    /// rule. The area element is the square root of a quadratic in v, so it is
+    /// smooth and the integral is accurate.
+    fn integrate_area_element(&self, v0: f32, v1: f32) -> f32 {
+        let n = AREA_INTEGRATION_INTERVALS;
+        let h = (v1 - v0) / n as f32;
+        let interior_sum: f32 = (1..n)
+            .map(|i| {
+                let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+                weight * self.area_element(v0 + i as f32 * h)
+            })
+            .sum();
+        h / 3.0 * (self.area_element(v0) + interior_sum + self.area_element(v1))
+    }
+
+    /// Returns the height v along the swept segment below which the fraction
+    /// `u` of the hyperboloid's area lies. The integral of the area element is
+    /// inverted with Newton's method, falling back to bisection whenever a
+    /// Newton step would leave the bracket around the solution.
+    fn invert_area_integral(&self, u: f32) -> f32 {
+        let target = u * self.area;
+        let tolerance = 1e-6 * target.max(f32::MIN_POSITIVE);
+        let (mut low, mut high) = (0.0, 1.0);
+        let mut v = u;
+        for _ in 0..MAX_AREA_INVERSION_STEPS {
+            let error = self.phi_max * self.integrate_area_element(0.0, v) - target;
+            if error.abs() <= tolerance {
+                break;
+            }
+            if error < 0.0 {
+                low = v;
+            } else {
+                high = v;
+            }
+            let next_v = v - error / (self.phi_max * self.area_element(v));
+            v = if next_v > low && next_v < high {
+                next_v
+            } else {
+                0.5 * (low + high)
+            };
+        }
+        v
+    }
+}
+
+impl<'a> Shape for Hyperboloid<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(-1.0 * self.r_max, -1.0 * self.r_max, self.z_min),
+            cgmath::Point3::new(self.r_max, self.r_max, self.z_max),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let (ray, (o_err, d_err)) = self.world_to_object().transform_with_error(ray);
+
+        // Initialize ray values.
+        let ox = EFloat32::new_with_err(ray.origin.x, o_err.x);
+        let oy = EFloat32::new_with_err(ray.origin.y, o_err.y);
+        let oz = EFloat32::new_with_err(ray.origin.z, o_err.z);
+        let dx = EFloat32::new_with_err(ray.direction.x, d_err.x);
+        let dy = EFloat32::new_with_err(ray.direction.y, d_err.y);
+        let dz = EFloat32::new_with_err(ray.direction.z, d_err.z);
+
+        // Compute quadratic hyperboloid coefficients.
+        let ah = EFloat32::new(self.ah);
+        let ch = EFloat32::new(self.ch);
+        let a = ah * dx * dx + ah * dy * dy - ch * dz * dz;
+        let b = EFloat32::new(2.0) * (ah * dx * ox + ah * dy * oy - ch * dz * oz);
+        let c = ah * ox * ox + ah * oy * oy - ch * oz * oz - EFloat32::new(1.0);
+
+        // Solve quadratic equation for t values and find the nearest hit that
+        // hasn't been clipped away.
+        let (t0, t1) = math::quadratic(a, b, c)?;
+        let (t_shape_hit, (p_hit, phi)) = nearest_quadric_hit(t0, t1, ray.t_max, |t| {
+            let p = ray.at_t(t);
+            clipped_hit(
+                p,
+                self.segment_azimuth(&p),
+                self.z_min,
+                self.z_max,
+                self.phi_max,
+            )
+        })?;
+
+        // Find parametric representation of hyperboloid hit.
+        let u = phi / self.phi_max;
+        let v = (p_hit.z - self.p1.z) / (self.p2.z - self.p1.z);
+
+        // Compute hyperboloid dpdu and dpdv.
+        let cos_phi = phi.cos();
+        let sin_phi = phi.sin();
+        let d = self.p2 - self.p1;
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv = Vector3::new(
+            d.x * cos_phi - d.y * sin_phi,
+            d.x * sin_phi + d.y * cos_phi,
+            d.z,
+        );
+
+        // Compute hyperboloid dndu and dndv.
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = self.phi_max * Vector3::new(-1.0 * dpdv.y, dpdv.x, 0.0);
+        let d2pdvv = Vector3::new(0.0, 0.0, 0.0);
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for hyperboloid intersection by evaluating the
+        // ray's parametric equation with error tracking.
+        let px = ox + t_shape_hit * dx;
+        let py = oy + t_shape_hit * dy;
+        let pz = oz + t_shape_hit * dz;
+        let p_error = Vector3::new(
+            px.absolute_error(),
+            py.absolute_error(),
+            pz.absolute_error(),
+        );
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((
+            f32::from(t_shape_hit),
+            self.object_to_world.transform(&interaction),
+        ))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.area
+    }
+
+    /// Samples the hyperboloid uniformly by area. The swept segment's height
+    /// v is sampled by numerically inverting the integral of the area element,
+    /// and phi is sampled uniformly.
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let v = self.invert_area_integral(u.x);
+        let phi = u.y * self.phi_max;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let d = self.p2 - self.p1;
+        let pr = self.p1 + v * d;
+        let p = Point3::new(
+            pr.x * cos_phi - pr.y * sin_phi,
+            pr.x * sin_phi + pr.y * cos_phi,
+            pr.z,
+        );
+
+        // The normal is dpdu x dpdv, with dpdu's factor of phi_max divided out.
+        let dpdv = Vector3::new(
+            d.x * cos_phi - d.y * sin_phi,
+            d.x * sin_phi + d.y * cos_phi,
+            d.z,
+        );
+        let n = Vector3::new(-1.0 * p.y, p.x, 0.0).cross(dpdv);
+        if n.magnitude2() == 0.0 {
+            return None;
+        }
+        let p_error = math::gamma(5) * p.to_vec().map(|f| f.abs());
+        let point = sampled_surface_point(self, p, p_error, n, Point2::new(u.y, v));
+        Some((point, 1.0 / self.surface_area()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Hyperboloid;
+    use super::HyperboloidError;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+    use std::f32::consts::PI;
+
+    /// Creates the hyperboloid swept by the segment from (1, 0, -1) to
+    /// (0, 1, 1), whose radius at height z is sqrt((1 + z^2) / 2).
+    fn hyperboloid(object_to_world: &Transform) -> Hyperboloid<'_> {
+        Hyperboloid::new(
+            object_to_world,
+            false,
+            Point3::new(1.0, 0.0, -1.0),
+            Point3::new(0.0, 1.0, 1.0),
+            360.0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn new_rejects_degenerate_segments() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let p = Point3::new(1.0, 0.0, 1.0);
+        assert_eq!(
+            Hyperboloid::new(&object_to_world, false, p, p, 360.0).err(),
+            Some(HyperboloidError::CoincidentPoints)
+        );
+        assert_eq!(
+            Hyperboloid::new(
+                &object_to_world,
+                false,
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+                360.0
+            )
+            .err(),
+            Some(HyperboloidError::HorizontalSegment)
+        );
+        assert_eq!(
+            Hyperboloid::new(
+                &object_to_world,
+                false,
+                Point3::new(1.0, 0.0, 1.0),
+                Point3::new(2.0, 0.0, 1.0),
+                360.0
+            )
+            .err(),
+            Some(HyperboloidError::HorizontalSegment)
+        );
+    }
+
+    #[test]
+    fn sample_area_is_uniform() {
+        // The area is 2pi times the integral of sqrt(1/2 + 3/4 z^2) over
+        // [-1, 1].
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let (a, b) = (0.5f32, 0.75f32);
+        let integral = |z: f32| {
+            0.5 * z * (a + b * z * z).sqrt() + a / (2.0 * b.sqrt()) * (z * (b / a).sqrt()).asinh()
+        };
+        let expected = 2.0 * PI * (integral(1.0) - integral(-1.0));
+        assert_area_sampling(&hyperboloid(&object_to_world), expected);
+    }
+
+    #[test]
+    fn sample_solid_angle_from_center() {
+        // Directions from the center hit the hyperboloid at most once, and do
+        // so when they pass inside the rims at 45 degrees from the z = 0 plane.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        assert_solid_angle_sampling(
+            &hyperboloid(&object_to_world),
+            Point3::new(0.3, -0.2, 0.1),
+            4.0 * PI * 0.5f32.sqrt(),
+        );
+    }
+}
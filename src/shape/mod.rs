@@ -1,21 +1,54 @@
-mod sphere;
-mod triangle;
+pub mod bilinear_patch;
+pub mod cone;
+pub mod curve;
+pub mod cylinder;
+pub mod disk;
+pub mod displacement;
+pub mod heightfield;
+pub mod hyperboloid;
+pub mod paraboloid;
+pub mod sdf;
+pub mod sphere;
+pub mod subdivision;
+pub mod torus;
+pub mod triangle;
 
 use crate::bounding_box;
 use crate::interaction;
+use crate::interaction::Interaction;
+use crate::interaction::SurfacePoint;
 use crate::ray;
-use crate::transform::Transform;
+use crate::texture::Texture;
+use crate::texture::TextureEvalContext;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
+use crate::vector::Normal3;
+use cgmath::InnerSpace;
+use cgmath::MetricSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+use efloat::EFloat32;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
 
 /// Describes the geometric properties of a primitive and provides a ray
 /// intersection function.
-trait Shape<'a> {
-    /// Returns a reference to the matrix that transforms the shape from object
+///
+/// The trait is object safe so that surface interactions can refer to the
+/// shape they lie on as a `&dyn Shape`.
+pub trait Shape {
+    /// Returns a reference to the transformation from the shape's object
     /// space to world space.
-    fn object_to_world(&self) -> &'a cgmath::Matrix4<f32>;
+    fn object_to_world(&self) -> &transform::Transform;
 
-    /// Returns a reference to the matrix that transforms the shape from world
-    /// space to object space.
-    fn world_to_object(&self) -> &'a cgmath::Matrix4<f32>;
+    /// Returns the transformation from world space to the shape's object
+    /// space.
+    fn world_to_object(&self) -> transform::Transform {
+        self.object_to_world().inverse()
+    }
 
     /// Returns a flag that indicates whether the shape's `object_to_world`
     /// transform matrix swaps the handedness of the object coordinate system.
@@ -36,28 +69,385 @@ trait Shape<'a> {
     /// the (0, `ray.t_max`) parametric range along the ray.
     ///
     /// `ray` is in world space, and the returned surface interaction is in
-    /// world space.
+    /// world space. If `test_alpha_texture` is true, hits that the shape's
+    /// alpha texture cuts out, if it has one, are ignored.
     fn ray_intersection(
         &self,
         ray: &ray::Ray,
         test_alpha_texture: bool,
     ) -> Option<(f32, interaction::SurfaceInteraction)>;
 
-    /// Returns a boolean indicating whether the ray intersects the shape.
+    /// Returns a boolean indicating whether the ray intersects the shape. If
+    /// `test_alpha_texture` is true, hits that the shape's shadow alpha
+    /// texture cuts out, if it has one, are ignored.
     fn does_ray_intersect(&self, ray: &ray::Ray, test_alpha_texture: bool) -> bool;
 
     /// Returns the surface area of the shape.
     fn surface_area(&self) -> f32;
+
+    /// Samples a point on the shape's surface from the uniform sample `u`.
+    /// Returns the sampled point in world space and the probability density of
+    /// sampling it, with respect to surface area. (p. 836)
+    ///
+    /// Returns `None` if the shape can't be sampled or if the sampled point is
+    /// degenerate. Like `surface_area`, the density assumes that the shape's
+    /// transform doesn't scale it.
+    fn sample_area(&self, _u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        None
+    }
+
+    /// Returns the probability density, with respect to surface area, of
+    /// sampling the given point on the shape's surface with `sample_area`.
+    /// The default is the density of sampling the surface uniformly.
+    fn pdf_area(&self, _point: &dyn Interaction) -> f32 {
+        1.0 / self.surface_area()
+    }
+
+    /// Samples a point on the shape's surface as seen from the reference
+    /// point, from the uniform sample `u`. Returns the sampled point
+    /// in world space and the probability density of sampling it, with
+    /// respect to solid angle at the reference point. (p. 837)
+    ///
+    /// The default samples the surface by area and converts the density to
+    /// solid angle. Shapes that subtend a small solid angle from the reference
+    /// point can do better by only sampling the part of the surface that is
+    /// potentially visible from it.
+    fn sample_solid_angle(
+        &self,
+        reference: &dyn Interaction,
+        u: Point2<f32>,
+    ) -> Option<(SurfacePoint, f32)> {
+        sample_solid_angle_by_area(self, reference, u)
+    }
+
+    /// Returns the probability density, with respect to solid angle at the
+    /// reference point, of sampling the point on the shape's surface in the
+    /// direction `wi` with `sample_solid_angle`. The density is zero if the
+    /// ray from the reference point in direction `wi` misses the shape.
+    /// (p. 838)
+    fn pdf_solid_angle(&self, reference: &dyn Interaction, wi: Vector3<f32>) -> f32 {
+        pdf_solid_angle_by_area(self, reference, wi)
+    }
+}
+
+/// Computes the partial derivatives of the surface normal with respect to U
+/// and V from the first and second partial derivatives of the surface position
+/// using the Weingarten equations. (p. 132)
+fn weingarten(
+    dpdu: Vector3<f32>,
+    dpdv: Vector3<f32>,
+    d2pduu: Vector3<f32>,
+    d2pduv: Vector3<f32>,
+    d2pdvv: Vector3<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    // Compute coefficients for the fundamental forms.
+    let e1 = dpdu.dot(dpdu);
+    let f1 = dpdu.dot(dpdv);
+    let g1 = dpdv.dot(dpdv);
+    let n = dpdu.cross(dpdv).normalize();
+    let e2 = n.dot(d2pduu);
+    let f2 = n.dot(d2pduv);
+    let g2 = n.dot(d2pdvv);
+
+    let inv_egf2 = 1.0 / (e1 * g1 - f1 * f1);
+    let dndu = ((f2 * f1 - e2 * g1) * inv_egf2) * dpdu + ((e2 * f1 - f2 * e1) * inv_egf2) * dpdv;
+    let dndv = ((g2 * f1 - f2 * g1) * inv_egf2) * dpdu + ((f2 * f1 - g2 * e1) * inv_egf2) * dpdv;
+    (dndu, dndv)
+}
+
+/// Returns the nearer of a quadric's two ray intersections, `t0` and `t1`,
+/// that lies within the (0, `t_max`) parametric range along the ray and that
+/// hasn't been clipped away, along with the result of `hit` for that
+/// intersection.
+///
+/// `hit` is given the parametric value of a candidate intersection and returns
+/// `None` if the intersection has been clipped away. The error bounds of the t
+/// values are used so that a hit is never reported when the true t value might
+/// lie outside the ray's range.
+fn nearest_quadric_hit<T, F>(
+    t0: EFloat32,
+    t1: EFloat32,
+    t_max: f32,
+    hit: F,
+) -> Option<(EFloat32, T)>
+where
+    F: Fn(f32) -> Option<T>,
+{
+    if t0.upper_bound() > t_max || t1.lower_bound() <= 0.0 {
+        return None;
+    }
+    if t0.lower_bound() > 0.0 {
+        if let Some(h) = hit(f32::from(t0)) {
+            return Some((t0, h));
+        }
+    }
+    if t1.upper_bound() > t_max {
+        return None;
+    }
+    hit(f32::from(t1)).map(|h| (t1, h))
+}
+
+/// Returns the object space point `p` on a quadric's surface along with its
+/// azimuth `phi`, or `None` if the point has been clipped away because it lies
+/// outside the quadric's [`z_min`, `z_max`] range or beyond its maximum
+/// azimuth `phi_max`.
+fn clipped_hit(
+    p: Point3<f32>,
+    phi: f32,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32,
+) -> Option<(Point3<f32>, f32)> {
+    if p.z < z_min || p.z > z_max || phi > phi_max {
+        None
+    } else {
+        Some((p, phi))
+    }
+}
+
+/// Returns a flag that indicates whether a ray's hit on a surface with the
+/// given alpha texture is kept. Hits where alpha is zero are discarded, and
+/// hits where it is fractional are kept with probability alpha. The choice is
+/// made by hashing the ray and the hit point, so that tracing the same ray
+/// again gives the same result. The `salt` is hashed in as well, which keeps
+/// the choices for different primitives and different textures tested at the
+/// same hit independent of each other.
+fn passes_alpha_test(
+    alpha: &dyn Texture<f32>,
+    interaction: &interaction::SurfaceInteraction,
+    ray: &ray::Ray,
+    salt: impl Hash,
+) -> bool {
+    let a = alpha.evaluate(&TextureEvalContext::from_interaction(interaction));
+    if a >= 1.0 {
+        return true;
+    }
+    if a <= 0.0 {
+        return false;
+    }
+
+    let p = interaction.point();
+    let mut hasher = DefaultHasher::new();
+    for f in [
+        ray.origin.x,
+        ray.origin.y,
+        ray.origin.z,
+        ray.direction.x,
+        ray.direction.y,
+        ray.direction.z,
+        p.x,
+        p.y,
+        p.z,
+    ]
+    .iter()
+    {
+        f.to_bits().hash(&mut hasher);
+    }
+    salt.hash(&mut hasher);
+    let u = (hasher.finish() >> 40) as f32 / (1u64 << 24) as f32;
+    u < a
 }
 
-// TODO: Remove and replace uses of GenericShape with Shape trait objects.
-pub struct GenericShape {
-    /// Flag that indicates whether the shape's normals should be flipped from
-    /// their original directions so that they point to the outside of the
-    /// shape.
-    pub reverse_orientation: bool,
+/// Samples a point on the shape's surface by area and converts the density of
+/// sampling it to solid angle at the reference point. (p. 837)
+fn sample_solid_angle_by_area<S: Shape + ?Sized>(
+    shape: &S,
+    reference: &dyn Interaction,
+    u: Point2<f32>,
+) -> Option<(SurfacePoint, f32)> {
+    let (mut point, pdf) = shape.sample_area(u)?;
+    point.set_time(reference.time());
+    let pdf = area_to_solid_angle_pdf(pdf, reference.point(), point.point(), point.normal()?)?;
+    Some((point, pdf))
+}
+
+/// Returns the probability density, with respect to solid angle at the
+/// reference point, of sampling the point in the direction `wi` with
+/// `sample_solid_angle_by_area`. (p. 838)
+fn pdf_solid_angle_by_area<S: Shape + ?Sized>(
+    shape: &S,
+    reference: &dyn Interaction,
+    wi: Vector3<f32>,
+) -> f32 {
+    // Intersect a ray from the reference point with the shape to find the
+    // point that would have been sampled.
+    let ray = reference.spawn_ray(wi);
+    let interaction = match shape.ray_intersection(&ray, false) {
+        Some((_, interaction)) => interaction,
+        None => return 0.0,
+    };
+    let normal = match interaction.normal() {
+        Some(n) => n,
+        None => return 0.0,
+    };
+    area_to_solid_angle_pdf(
+        shape.pdf_area(&interaction),
+        reference.point(),
+        interaction.point(),
+        normal,
+    )
+    .unwrap_or(0.0)
+}
+
+/// Converts the probability density of sampling `point`, with respect to
+/// surface area, into a density with respect to solid angle as seen from
+/// `reference`. `normal` is the surface normal at `point`. Returns `None` if
+/// the density is infinite because the point coincides with the reference
+/// point or is seen edge-on. (p. 838)
+fn area_to_solid_angle_pdf(
+    pdf: f32,
+    reference: Point3<f32>,
+    point: Point3<f32>,
+    normal: Vector3<f32>,
+) -> Option<f32> {
+    let distance2 = reference.distance2(point);
+    let cos_theta = normal.dot(reference - point).abs() / distance2.sqrt();
+    if distance2 == 0.0 || cos_theta == 0.0 {
+        return None;
+    }
+    let pdf = pdf * distance2 / cos_theta;
+    if pdf.is_finite() {
+        Some(pdf)
+    } else {
+        None
+    }
+}
+
+/// Transforms a point sampled on the surface of a shape from the shape's
+/// object space to world space. `normal` is the object space normal
+/// dp/du x dp/dv, which is flipped in the same way as the normal of a
+/// `SurfaceInteraction` on the shape.
+fn sampled_surface_point<S: Shape + ?Sized>(
+    shape: &S,
+    point: Point3<f32>,
+    point_error_bound: Vector3<f32>,
+    normal: Vector3<f32>,
+    uv: Point2<f32>,
+) -> SurfacePoint {
+    let object_to_world = shape.object_to_world();
+    let (point, point_error_bound) =
+        object_to_world.transform_with_error_bound(&point, &point_error_bound);
+    let normal = object_to_world.transform(&Normal3(normal)).0.normalize();
+    let normal = if shape.reverse_orientation() ^ shape.object_to_world_swaps_handedness() {
+        -1.0 * normal
+    } else {
+        normal
+    };
+    SurfacePoint::new(point, point_error_bound, normal, uv)
+}
+
+/// Returns the angle of the point around the z axis, in the range [0, 2pi).
+fn azimuth(p: &cgmath::Point3<f32>) -> f32 {
+    let phi = p.y.atan2(p.x);
+    if phi < 0.0 {
+        phi + 2.0 * std::f32::consts::PI
+    } else {
+        phi
+    }
+}
+
+/// Returns the key used to identify the undirected edge between the vertices
+/// `a` and `b` of a mesh, which is the same for both directions of the edge.
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Monte Carlo checks of the sampling methods that are shared by the tests of
+/// the individual shapes. The checks use a stratified grid of samples, so they
+/// are deterministic.
+#[cfg(test)]
+mod test_util {
+    use super::Shape;
+    use crate::interaction::Interaction;
+    use crate::interaction::SurfacePoint;
+    use cgmath::Point2;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    /// The number of strata along each dimension of the sample grid.
+    const GRID_SIZE: usize = 256;
+
+    /// Returns the centers of the cells of a `GRID_SIZE` by `GRID_SIZE` grid
+    /// over [0, 1)^2.
+    fn sample_grid() -> impl Iterator<Item = Point2<f32>> {
+        (0..GRID_SIZE * GRID_SIZE).map(|i| {
+            Point2::new(
+                ((i % GRID_SIZE) as f32 + 0.5) / GRID_SIZE as f32,
+                ((i / GRID_SIZE) as f32 + 0.5) / GRID_SIZE as f32,
+            )
+        })
+    }
+
+    /// Asserts that `actual` is within the relative `tolerance` of `expected`.
+    pub(crate) fn assert_close(actual: f32, expected: f32, tolerance: f32, what: &str) {
+        assert!(
+            (actual - expected).abs() <= tolerance * expected.abs(),
+            "{} is {} but expected {}",
+            what,
+            actual,
+            expected
+        );
+    }
+
+    /// Asserts that sampling the shape by area is consistent with its area and
+    /// with `pdf_area`. The expected value of 1/pdf is the surface area.
+    pub(crate) fn assert_area_sampling(shape: &dyn Shape, expected_area: f32) {
+        let mut inverse_pdf_sum = 0.0f64;
+        for u in sample_grid() {
+            let (point, pdf) = shape.sample_area(u).expect("area sampling failed");
+            assert!(pdf > 0.0 && pdf.is_finite(), "bad area density {}", pdf);
+            assert_close(shape.pdf_area(&point), pdf, 1e-2, "pdf_area");
+            inverse_pdf_sum += 1.0 / f64::from(pdf);
+        }
+        let estimate = inverse_pdf_sum / (GRID_SIZE * GRID_SIZE) as f64;
+        assert_close(estimate as f32, expected_area, 1e-2, "estimated area");
+    }
 
-    /// Flag that indicates whether the shape's `transform` swaps the handedness
-    /// of the object coordinate system for the shape.
-    pub transform_swaps_handedness: bool,
+    /// Asserts that sampling the shape by solid angle from the reference point
+    /// is consistent with the solid angle that the shape subtends from it and
+    /// with `pdf_solid_angle`. The expected value of 1/pdf is the solid angle,
+    /// as long as no ray from the reference point hits the shape twice.
+    pub(crate) fn assert_solid_angle_sampling(
+        shape: &dyn Shape,
+        reference: Point3<f32>,
+        expected_solid_angle: f32,
+    ) {
+        let reference = SurfacePoint::new(
+            reference,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Point2::new(0.0, 0.0),
+        );
+        let mut inverse_pdf_sum = 0.0f64;
+        for u in sample_grid() {
+            let (point, pdf) = match shape.sample_solid_angle(&reference, u) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            assert!(
+                pdf > 0.0 && pdf.is_finite(),
+                "bad solid angle density {}",
+                pdf
+            );
+            let wi = point.point() - reference.point();
+            assert_close(
+                shape.pdf_solid_angle(&reference, wi),
+                pdf,
+                1e-2,
+                "pdf_solid_angle",
+            );
+            inverse_pdf_sum += 1.0 / f64::from(pdf);
+        }
+        let estimate = inverse_pdf_sum / (GRID_SIZE * GRID_SIZE) as f64;
+        assert_close(
+            estimate as f32,
+            expected_solid_angle,
+            1e-2,
+            "estimated solid angle",
+        );
+    }
 }
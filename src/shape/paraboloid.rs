@@ -0,0 +1,241 @@
+use super::azimuth;
+use super::clipped_hit;
+use super::nearest_quadric_hit;
+use super::sampled_surface_point;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
+use crate::transform::SwapHandedness;
+use cgmath::EuclideanSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+use efloat::EFloat32;
+
+/// A paraboloid centered on the z axis in object space, with its vertex at the
+/// origin and opening towards the positive z axis.
+pub struct Paraboloid<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Paraboloid-specific fields
+    radius: f32,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32,
+}
+
+impl<'a> Paraboloid<'a> {
+    /// Creates a paraboloid that has the given `radius` at its maximum z value
+    /// and is clipped at `z0` and `z1` along the z axis and swept around the z
+    /// axis up to `phi_max` degrees.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        radius: f32,
+        z0: f32,
+        z1: f32,
+        phi_max: f32,
+    ) -> Self {
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Paraboloid-specific fields
+            radius,
+            z_min: z0.min(z1),
+            z_max: z0.max(z1),
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+        }
+    }
+}
+
+impl<'a> Shape for Paraboloid<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(-1.0 * self.radius, -1.0 * self.radius, self.z_min),
+            cgmath::Point3::new(self.radius, self.radius, self.z_max),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let (ray, (o_err, d_err)) = self.world_to_object().transform_with_error(ray);
+
+        // Initialize ray values.
+        let ox = EFloat32::new_with_err(ray.origin.x, o_err.x);
+        let oy = EFloat32::new_with_err(ray.origin.y, o_err.y);
+        let oz = EFloat32::new_with_err(ray.origin.z, o_err.z);
+        let dx = EFloat32::new_with_err(ray.direction.x, d_err.x);
+        let dy = EFloat32::new_with_err(ray.direction.y, d_err.y);
+        let dz = EFloat32::new_with_err(ray.direction.z, d_err.z);
+
+        // Compute quadratic paraboloid coefficients.
+        let k =
+            EFloat32::new(self.z_max) / (EFloat32::new(self.radius) * EFloat32::new(self.radius));
+        let a = k * (dx * dx + dy * dy);
+        let b = EFloat32::new(2.0) * k * (dx * ox + dy * oy) - dz;
+        let c = k * (ox * ox + oy * oy) - oz;
+
+        // Solve quadratic equation for t values and find the nearest hit that
+        // hasn't been clipped away.
+        let (t0, t1) = math::quadratic(a, b, c)?;
+        let (t_shape_hit, (p_hit, phi)) = nearest_quadric_hit(t0, t1, ray.t_max, |t| {
+            let p = ray.at_t(t);
+            clipped_hit(p, azimuth(&p), self.z_min, self.z_max, self.phi_max)
+        })?;
+
+        // Find parametric representation of paraboloid hit.
+        let u = phi / self.phi_max;
+        let z_range = self.z_max - self.z_min;
+        let v = (p_hit.z - self.z_min) / z_range;
+
+        // Compute paraboloid dpdu and dpdv.
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv =
+            z_range * Vector3::new(p_hit.x / (2.0 * p_hit.z), p_hit.y / (2.0 * p_hit.z), 1.0);
+
+        // Compute paraboloid dndu and dndv.
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = z_range
+            * self.phi_max
+            * Vector3::new(
+                -1.0 * p_hit.y / (2.0 * p_hit.z),
+                p_hit.x / (2.0 * p_hit.z),
+                0.0,
+            );
+        let d2pdvv = -1.0
+            * z_range
+            * z_range
+            * Vector3::new(
+                p_hit.x / (4.0 * p_hit.z * p_hit.z),
+                p_hit.y / (4.0 * p_hit.z * p_hit.z),
+                0.0,
+            );
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for paraboloid intersection by evaluating the
+        // ray's parametric equation with error tracking.
+        let px = ox + t_shape_hit * dx;
+        let py = oy + t_shape_hit * dy;
+        let pz = oz + t_shape_hit * dz;
+        let p_error = Vector3::new(
+            px.absolute_error(),
+            py.absolute_error(),
+            pz.absolute_error(),
+        );
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((
+            f32::from(t_shape_hit),
+            self.object_to_world.transform(&interaction),
+        ))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        let radius2 = self.radius * self.radius;
+        let k = 4.0 * self.z_max / radius2;
+        (radius2 * radius2 * self.phi_max / (12.0 * self.z_max * self.z_max))
+            * ((k * self.z_max + 1.0).powf(1.5) - (k * self.z_min + 1.0).powf(1.5))
+    }
+
+    /// Samples the paraboloid uniformly by area. The area of the part of the
+    /// paraboloid below height z is proportional to (kz + 1)^1.5, which is
+    /// inverted to sample z.
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let radius2 = self.radius * self.radius;
+        let k = 4.0 * self.z_max / radius2;
+        let s = math::lerp(
+            u.x,
+            (k * self.z_min + 1.0).powf(1.5),
+            (k * self.z_max + 1.0).powf(1.5),
+        );
+        let z = math::clamp((s.powf(2.0 / 3.0) - 1.0) / k, self.z_min, self.z_max);
+        let hit_radius = self.radius * (z / self.z_max).max(0.0).sqrt();
+        let phi = u.y * self.phi_max;
+        let p = Point3::new(hit_radius * phi.cos(), hit_radius * phi.sin(), z);
+
+        // The normal is dpdu x dpdv, with the z component's division by z
+        // cancelled using x^2 + y^2 = z r^2 / z_max.
+        let n = Vector3::new(p.x, p.y, -1.0 * radius2 / (2.0 * self.z_max));
+        let p_error = math::gamma(5) * p.to_vec().map(|f| f.abs());
+        let v = (z - self.z_min) / (self.z_max - self.z_min);
+        let point = sampled_surface_point(self, p, p_error, n, Point2::new(u.y, v));
+        Some((point, 1.0 / self.surface_area()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Paraboloid;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sample_area_is_uniform() {
+        // The area of the paraboloid z = 2 r^2 between heights 0.5 and 2.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let paraboloid = Paraboloid::new(&object_to_world, false, 1.0, 0.5, 2.0, 270.0);
+        let area_below = |z: f32| PI / 24.0 * (8.0 * z + 1.0).powf(1.5);
+        assert_area_sampling(&paraboloid, 0.75 * (area_below(2.0) - area_below(0.5)));
+    }
+
+    #[test]
+    fn sample_solid_angle_from_inside() {
+        // Every direction from inside the paraboloid hits it once, except for
+        // those that leave through the opening at its top.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let paraboloid = Paraboloid::new(&object_to_world, false, 1.0, 0.0, 2.0, 360.0);
+        let expected = 2.0 * PI * (1.0 + 0.5f32.sqrt());
+        assert_solid_angle_sampling(&paraboloid, Point3::new(0.3, -0.2, 1.1), expected);
+    }
+}
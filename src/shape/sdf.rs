@@ -0,0 +1,383 @@
+use super::Shape;
+use crate::bounding_box::Bounds3;
+use crate::interaction::SurfaceInteraction;
+use crate::math;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use crate::vector;
+use cgmath::ElementWise;
+use cgmath::EuclideanSpace;
+use cgmath::InnerSpace;
+use cgmath::Point2;
+use cgmath::Point3;
+use cgmath::Vector3;
+
+/// The largest number of steps that sphere tracing takes along a ray before
+/// giving up on finding the surface. Rays that graze the surface can take
+/// many small steps without reaching it.
+const MAX_SPHERE_TRACING_STEPS: usize = 512;
+
+/// The number of grid cells along each axis of the bounds that are sampled to
+/// estimate the surface area.
+const AREA_ESTIMATION_RESOLUTION: usize = 64;
+
+/// The distance from the surface, relative to the size of the shape's bounds,
+/// at which sphere tracing considers a point to be on the surface.
+const RELATIVE_SURFACE_TOLERANCE: f32 = 1e-5;
+
+/// A function that returns the signed distance from a point in object space to
+/// the nearest point on a surface. The distance is negative inside the
+/// surface and positive outside it.
+///
+/// Sphere tracing steps along rays by the returned distance, so the function
+/// must never overestimate the distance to the surface. Functions that can
+/// overestimate it by up to a known factor can still be used by giving that
+/// factor to `Sdf::lipschitz_bound`.
+pub trait SignedDistance {
+    fn distance(&self, p: Point3<f32>) -> f32;
+}
+
+/// Any function of a point can be used as a signed distance function, which
+/// allows surfaces to be modeled with closures.
+impl<F> SignedDistance for F
+where
+    F: Fn(Point3<f32>) -> f32,
+{
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        self(p)
+    }
+}
+
+/// A shape whose surface is the zero set of a signed distance function.
+///
+/// Rays are intersected by sphere tracing: starting where the ray enters the
+/// shape's bounds, the ray is repeatedly advanced by the distance to the
+/// surface at its current point, which can't step past the surface, until the
+/// distance is within a small tolerance of zero.
+///
+/// Surface normals are computed from the gradient of the distance function
+/// with central differences. Signed distance functions have no natural
+/// parameterization, so the (u, v) coordinates of every hit are (0, 0) and the
+/// partial derivatives form an arbitrary tangent frame around the normal.
+pub struct Sdf<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Sdf-specific fields
+    distance_function: &'a dyn SignedDistance,
+
+    /// The object space bounds that contain the surface. Sphere tracing only
+    /// searches for the surface within them.
+    bounds: Bounds3<f32>,
+
+    /// The factor by which the distance function may overestimate the
+    /// distance to the surface.
+    lipschitz_bound: f32,
+
+    /// The distance from the surface at which a point is considered to be on
+    /// the surface.
+    surface_tolerance: f32,
+
+    /// The estimated surface area, which is computed once when the shape is
+    /// created.
+    area: f32,
+}
+
+impl<'a> Sdf<'a> {
+    /// Creates a shape whose surface is the zero set of `distance_function`
+    /// within the object space `bounds`.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        distance_function: &'a dyn SignedDistance,
+        bounds: Bounds3<f32>,
+    ) -> Self {
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Sdf-specific fields
+            distance_function,
+            bounds,
+            lipschitz_bound: 1.0,
+            surface_tolerance: RELATIVE_SURFACE_TOLERANCE * bounds.diagonal().magnitude(),
+            area: estimate_area(distance_function, &bounds),
+        }
+    }
+
+    /// Sets the factor by which the distance function may overestimate the
+    /// distance to the surface. Sphere tracing takes steps that are shorter by
+    /// this factor, so it doesn't step past the surface.
+    pub fn lipschitz_bound(mut self, lipschitz_bound: f32) -> Self {
+        self.lipschitz_bound = lipschitz_bound.max(1.0);
+        self
+    }
+
+    /// Sphere traces the object space ray and returns the parametric t value
+    /// of the first point where it meets the surface.
+    fn sphere_trace(&self, ray: &Ray) -> Option<f32> {
+        let (t_enter, t_exit) = self.bounds.ray_intersection(ray)?;
+        let direction_length = ray.direction.magnitude();
+        if direction_length == 0.0 {
+            return None;
+        }
+
+        let mut t = t_enter;
+        for _ in 0..MAX_SPHERE_TRACING_STEPS {
+            let distance = self.distance_function.distance(ray.at_t(t)).abs();
+            if distance < self.surface_tolerance {
+                return if t > 0.0 && t < ray.t_max {
+                    Some(t)
+                } else {
+                    None
+                };
+            }
+            t += distance / (self.lipschitz_bound * direction_length);
+            if t > t_exit {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Returns the normalized gradient of the distance function at `p`,
+    /// estimated with central differences.
+    fn gradient(&self, p: Point3<f32>) -> Vector3<f32> {
+        let h = self.surface_tolerance;
+        let f = |offset: Vector3<f32>| {
+            self.distance_function.distance(p + offset)
+                - self.distance_function.distance(p - offset)
+        };
+        let gradient = Vector3::new(
+            f(Vector3::new(h, 0.0, 0.0)),
+            f(Vector3::new(0.0, h, 0.0)),
+            f(Vector3::new(0.0, 0.0, h)),
+        );
+        if gradient.magnitude2() > 0.0 {
+            gradient.normalize()
+        } else {
+            Vector3::new(0.0, 0.0, 1.0)
+        }
+    }
+}
+
+impl<'a> Shape for Sdf<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> Bounds3<f32> {
+        self.bounds
+    }
+
+    fn world_bound(&self) -> Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let ray = self.world_to_object().transform(ray);
+        let t_shape_hit = self.sphere_trace(&ray)?;
+        let p_hit = ray.at_t(t_shape_hit);
+
+        // Build a tangent frame around the normal so that the cross product of
+        // the partial derivatives is the normal.
+        let normal = self.gradient(p_hit);
+        let (dpdu, dpdv) = vector::coordinate_system(normal);
+
+        // Compute error bounds for the hit. The hit point lies within the
+        // surface tolerance of the surface, on either side of it, so the bound
+        // is wide enough that rays spawned from the point start outside the
+        // tolerance band and don't immediately find the surface again.
+        let p_error = Vector3::new(1.0, 1.0, 1.0) * (3.0 * self.surface_tolerance)
+            + math::gamma(3) * p_hit.to_vec().map(|f| f.abs());
+
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            Point2::new(0.0, 0.0),
+            dpdu,
+            dpdv,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+
+        Some((t_shape_hit, self.object_to_world.transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, _test_alpha_texture: bool) -> bool {
+        let ray = self.world_to_object().transform(ray);
+        self.sphere_trace(&ray).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.area
+    }
+}
+
+/// Estimates the area of the zero set of the distance function within the
+/// bounds.
+fn estimate_area(distance_function: &dyn SignedDistance, bounds: &Bounds3<f32>) -> f32 {
+    // Estimate the area with the coarea formula: integrating a narrow
+    // kernel of the distance over the bounds gives the area of the zero
+    // set, since the gradient of a signed distance function has unit
+    // length. The kernel is as wide as a grid cell so that every part of
+    // the surface contributes.
+    let n = AREA_ESTIMATION_RESOLUTION;
+    let cell_size = bounds.diagonal() / n as f32;
+    let kernel_width = cell_size.x.max(cell_size.y).max(cell_size.z);
+    if kernel_width <= 0.0 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                let offset = Vector3::new(i as f32 + 0.5, j as f32 + 0.5, k as f32 + 0.5);
+                let p = bounds.min() + cell_size.mul_element_wise(offset);
+                let distance = distance_function.distance(p).abs();
+                sum += (1.0 - distance / kernel_width).max(0.0);
+            }
+        }
+    }
+    sum * cell_size.x * cell_size.y * cell_size.z / kernel_width
+}
+
+/// A sphere centered at the origin.
+pub struct SphereSdf {
+    pub radius: f32,
+}
+
+impl SignedDistance for SphereSdf {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        p.to_vec().magnitude() - self.radius
+    }
+}
+
+/// An axis-aligned box centered at the origin.
+pub struct BoxSdf {
+    /// The distances from the center to the faces along each axis.
+    pub half_extents: Vector3<f32>,
+}
+
+impl SignedDistance for BoxSdf {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let q = p.to_vec().map(|f| f.abs()) - self.half_extents;
+        let outside = q.map(|f| f.max(0.0)).magnitude();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside
+    }
+}
+
+/// A torus centered at the origin with the z axis running through its hole,
+/// like `Torus`.
+pub struct TorusSdf {
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl SignedDistance for TorusSdf {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let radial = (p.x * p.x + p.y * p.y).sqrt() - self.major_radius;
+        (radial * radial + p.z * p.z).sqrt() - self.minor_radius
+    }
+}
+
+/// A capped cylinder centered at the origin and aligned with the z axis.
+pub struct CylinderSdf {
+    pub radius: f32,
+
+    /// The distance from the center to each cap.
+    pub half_height: f32,
+}
+
+impl SignedDistance for CylinderSdf {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let radial = (p.x * p.x + p.y * p.y).sqrt() - self.radius;
+        let axial = p.z.abs() - self.half_height;
+        let outside = (radial.max(0.0).powi(2) + axial.max(0.0).powi(2)).sqrt();
+        let inside = radial.max(axial).min(0.0);
+        outside + inside
+    }
+}
+
+/// The union of two surfaces. The surfaces are blended together within
+/// `smoothness` of where they meet, and a smoothness of zero gives the exact
+/// union.
+pub struct SmoothUnion<A, B> {
+    pub a: A,
+    pub b: B,
+    pub smoothness: f32,
+}
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.smoothness)
+    }
+}
+
+/// The part of surface `a` that is outside surface `b`, blended within
+/// `smoothness` of where they meet.
+pub struct SmoothSubtraction<A, B> {
+    pub a: A,
+    pub b: B,
+    pub smoothness: f32,
+}
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for SmoothSubtraction<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        -1.0 * smooth_min(
+            -1.0 * self.a.distance(p),
+            self.b.distance(p),
+            self.smoothness,
+        )
+    }
+}
+
+/// The intersection of two surfaces, blended within `smoothness` of where
+/// they meet.
+pub struct SmoothIntersection<A, B> {
+    pub a: A,
+    pub b: B,
+    pub smoothness: f32,
+}
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for SmoothIntersection<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        -1.0 * smooth_min(
+            -1.0 * self.a.distance(p),
+            -1.0 * self.b.distance(p),
+            self.smoothness,
+        )
+    }
+}
+
+/// Returns the minimum of `a` and `b`, blended with a polynomial where they
+/// are within `k` of each other. The blend only lowers the minimum, so the
+/// result still never overestimates the distance to the combined surface.
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = math::clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
@@ -1,17 +1,40 @@
+use super::azimuth;
+use super::clipped_hit;
+use super::nearest_quadric_hit;
+use super::pdf_solid_angle_by_area;
+use super::sample_solid_angle_by_area;
+use super::sampled_surface_point;
+use super::weingarten;
 use super::Shape;
 use crate::bounding_box;
+use crate::interaction::Interaction;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
 use crate::ray::Ray;
+use crate::sampling;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::ApplyTransformWithError;
 use crate::transform::SwapHandedness;
-use crate::transform::Transform;
+use crate::vector;
+use cgmath::EuclideanSpace;
+use cgmath::InnerSpace;
+use cgmath::MetricSpace;
+use cgmath::Point2;
 use cgmath::Point3;
 use cgmath::Vector3;
 use efloat::EFloat32;
 
+/// The squared sine of the half-angle, about 1.5 degrees, below which the cone
+/// of directions subtended by a sphere is sampled with a Taylor expansion.
+/// (p. 841)
+const SMALL_CONE_SIN_THETA_MAX2: f32 = 0.00068523;
+
 /// A sphere centered at the origin in object space.
 pub struct Sphere<'a> {
     // Generic shape fields
-    object_to_world: &'a cgmath::Matrix4<f32>,
-    world_to_object: &'a cgmath::Matrix4<f32>,
+    object_to_world: &'a transform::Transform,
     object_to_world_swaps_handedness: bool,
     reverse_orientation: bool,
 
@@ -25,39 +48,84 @@ pub struct Sphere<'a> {
 }
 
 impl<'a> Sphere<'a> {
+    /// Creates a sphere that is clipped at `z_min` and `z_max` along the z axis
+    /// and swept around the z axis up to `phi_max` degrees.
     pub fn new(
-        object_to_world: &'a cgmath::Matrix4<f32>,
-        world_to_object: &'a cgmath::Matrix4<f32>,
+        object_to_world: &'a transform::Transform,
         reverse_orientation: bool,
         radius: f32,
         z_min: f32,
         z_max: f32,
         phi_max: f32,
     ) -> Self {
+        let (z_min, z_max) = (
+            math::clamp(z_min.min(z_max), -1.0 * radius, radius),
+            math::clamp(z_min.max(z_max), -1.0 * radius, radius),
+        );
         Self {
             // Generic shape fields
             object_to_world,
-            world_to_object,
             object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
             reverse_orientation,
             // Sphere-specific fields
             radius,
             z_min,
             z_max,
-            theta_min: 0.0,
-            theta_max: 0.0,
-            phi_max: 0.0,
+            theta_min: math::clamp(z_min / radius, -1.0, 1.0).acos(),
+            theta_max: math::clamp(z_max / radius, -1.0, 1.0).acos(),
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
         }
     }
-}
 
-impl<'a> Shape<'a> for Sphere<'a> {
-    fn object_to_world(&self) -> &'a cgmath::Matrix4<f32> {
-        self.object_to_world
+    /// Returns the object space point on the sphere's surface that is hit by
+    /// the ray at parametric value `t`.
+    fn hit_point(&self, ray: &Ray, t: f32) -> Point3<f32> {
+        let mut p = ray.at_t(t);
+
+        // Refine the sphere intersection point by reprojecting it onto the
+        // surface. (p. 225)
+        p *= self.radius / p.distance(Point3::origin());
+        if p.x == 0.0 && p.y == 0.0 {
+            p.x = 1e-5 * self.radius;
+        }
+        p
     }
 
-    fn world_to_object(&self) -> &'a cgmath::Matrix4<f32> {
-        self.world_to_object
+    /// Returns the sampled point on the sphere's surface at the object space
+    /// point `p`, which must lie on the sphere.
+    fn sampled_point(&self, p: Point3<f32>) -> SurfacePoint {
+        // The sphere's dpdu x dpdv points away from its center.
+        let n = p.to_vec();
+        let p_error = math::gamma(5) * p.to_vec().map(|f| f.abs());
+        let phi = azimuth(&p);
+        let theta = math::clamp(p.z / self.radius, -1.0, 1.0).acos();
+        let uv = Point2::new(
+            phi / self.phi_max,
+            (theta - self.theta_min) / (self.theta_max - self.theta_min),
+        );
+        sampled_surface_point(self, p, p_error, n, uv)
+    }
+
+    /// Returns the cosine of the half-angle of the cone of directions that the
+    /// sphere subtends from the object space reference point. Returns `None` if
+    /// the sphere has been clipped, or if the reference point is inside the
+    /// sphere and so isn't surrounded by a cone.
+    fn cone_cos_theta_max(&self, reference: Point3<f32>) -> Option<f32> {
+        let is_clipped = self.z_min > -1.0 * self.radius
+            || self.z_max < self.radius
+            || self.phi_max < 2.0 * std::f32::consts::PI;
+        let distance_to_center2 = reference.to_vec().magnitude2();
+        if is_clipped || distance_to_center2 <= self.radius * self.radius {
+            return None;
+        }
+        let sin_theta_max2 = self.radius * self.radius / distance_to_center2;
+        Some((1.0 - sin_theta_max2).max(0.0).sqrt())
+    }
+}
+
+impl<'a> Shape for Sphere<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
     }
 
     fn object_to_world_swaps_handedness(&self) -> bool {
@@ -82,13 +150,9 @@ impl<'a> Shape<'a> for Sphere<'a> {
     fn ray_intersection(
         &self,
         ray: &crate::ray::Ray,
-        test_alpha_texture: bool,
+        _test_alpha_texture: bool,
     ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
-        let ray = self.object_to_world.transform(ray); // TODO: Return o_err and d_err too.
-                                                       // let (o_err, d_err) = ...from transform...
-
-        let o_err = Point3::new(0.0, 0.0, 0.0);
-        let d_err = Vector3::new(0.0, 0.0, 0.0);
+        let (ray, (o_err, d_err)) = self.world_to_object().transform_with_error(ray);
 
         // Initialize ray values.
         let ox = EFloat32::new_with_err(ray.origin.x, o_err.x);
@@ -104,14 +168,79 @@ impl<'a> Shape<'a> for Sphere<'a> {
         let c =
             ox * ox + oy * oy + oz * oz - EFloat32::new(self.radius) * EFloat32::new(self.radius);
 
-        // TODO: Solve quadratic equation for t values.
-        // TODO: Compute sphere hit position and phi.
-        // TODO: Test sphere intersection against clipping parameters.
-        // TODO: Find parametric representation of sphere hit.
-        // TODO: Compute error bounds for sphere intersection.
-        // TODO: Initialize SurfaceInteraction from parametric information.
-        // TODO: Update tHit for quadric intersection.
-        todo!()
+        // Solve quadratic equation for t values.
+        let (t0, t1) = math::quadratic(a, b, c)?;
+
+        // Find the nearest hit that hasn't been clipped away. The z range only
+        // clips the sphere where it's narrower than the sphere itself, so that
+        // hits at the poles aren't lost to round-off in the reprojection.
+        let z_min = if self.z_min > -1.0 * self.radius {
+            self.z_min
+        } else {
+            f32::NEG_INFINITY
+        };
+        let z_max = if self.z_max < self.radius {
+            self.z_max
+        } else {
+            f32::INFINITY
+        };
+        let (t_shape_hit, (p_hit, phi)) = nearest_quadric_hit(t0, t1, ray.t_max, |t| {
+            let p = self.hit_point(&ray, t);
+            clipped_hit(p, azimuth(&p), z_min, z_max, self.phi_max)
+        })?;
+
+        // Find parametric representation of sphere hit.
+        let u = phi / self.phi_max;
+        let theta = math::clamp(p_hit.z / self.radius, -1.0, 1.0).acos();
+        let v = (theta - self.theta_min) / (self.theta_max - self.theta_min);
+
+        // Compute sphere dpdu and dpdv.
+        let z_radius = (p_hit.x * p_hit.x + p_hit.y * p_hit.y).sqrt();
+        let cos_phi = p_hit.x / z_radius;
+        let sin_phi = p_hit.y / z_radius;
+        let sin_theta = (1.0 - (p_hit.z / self.radius) * (p_hit.z / self.radius))
+            .max(0.0)
+            .sqrt();
+        let theta_range = self.theta_max - self.theta_min;
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv = theta_range
+            * Vector3::new(
+                p_hit.z * cos_phi,
+                p_hit.z * sin_phi,
+                -1.0 * self.radius * sin_theta,
+            );
+
+        // Compute sphere dndu and dndv from the second partial derivatives
+        // using the Weingarten equations. (p. 132)
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv =
+            theta_range * p_hit.z * self.phi_max * Vector3::new(-1.0 * sin_phi, cos_phi, 0.0);
+        let d2pdvv = -1.0 * theta_range * theta_range * p_hit.to_vec();
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for sphere intersection. The reprojection of
+        // the hit point onto the surface bounds the error. (p. 225)
+        let p_error = math::gamma(5) * p_hit.to_vec().map(|f| f.abs());
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        // Update t_hit for quadric intersection.
+        Some((
+            f32::from(t_shape_hit),
+            self.object_to_world.transform(&interaction),
+        ))
     }
 
     fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
@@ -119,6 +248,129 @@ impl<'a> Shape<'a> for Sphere<'a> {
     }
 
     fn surface_area(&self) -> f32 {
-        todo!()
+        self.phi_max * self.radius * (self.z_max - self.z_min)
+    }
+
+    /// Samples the sphere uniformly by area, which is uniform in both z and
+    /// phi. (p. 838)
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let z = math::lerp(u.x, self.z_min, self.z_max);
+        let z_radius = (self.radius * self.radius - z * z).max(0.0).sqrt();
+        let phi = u.y * self.phi_max;
+        let p = Point3::new(z_radius * phi.cos(), z_radius * phi.sin(), z);
+        Some((self.sampled_point(p), 1.0 / self.surface_area()))
+    }
+
+    /// Samples the cone of directions that a full sphere subtends from a
+    /// reference point outside it, which only generates points on the part of
+    /// the sphere that is visible from the reference point. Clipped spheres and
+    /// reference points inside the sphere are sampled by area. (p. 840)
+    fn sample_solid_angle(
+        &self,
+        reference: &dyn Interaction,
+        u: Point2<f32>,
+    ) -> Option<(SurfacePoint, f32)> {
+        let reference_object = self.world_to_object().transform(&reference.point());
+        let cos_theta_max = match self.cone_cos_theta_max(reference_object) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return sample_solid_angle_by_area(self, reference, u),
+        };
+
+        // Compute the coordinate system for sphere sampling, with the z axis
+        // pointing from the reference point to the sphere's center.
+        let distance_to_center = reference_object.to_vec().magnitude();
+        let wc = -1.0 * reference_object.to_vec() / distance_to_center;
+        let (wc_x, wc_y) = vector::coordinate_system(wc);
+
+        // Sample the angle theta within the cone subtended by the sphere. A
+        // Taylor expansion is used for small cones, where computing
+        // 1 - cos(theta)^2 would be inaccurate.
+        let sin_theta_max2 = self.radius * self.radius / (distance_to_center * distance_to_center);
+        let (sin_theta2, cos_theta) = if sin_theta_max2 < SMALL_CONE_SIN_THETA_MAX2 {
+            let sin_theta2 = sin_theta_max2 * u.x;
+            (sin_theta2, (1.0 - sin_theta2).sqrt())
+        } else {
+            let cos_theta = (1.0 - u.x) + u.x * cos_theta_max;
+            ((1.0 - cos_theta * cos_theta).max(0.0), cos_theta)
+        };
+        let phi = u.y * 2.0 * std::f32::consts::PI;
+
+        // Compute the angle alpha from the center of the sphere to the point
+        // on the sphere hit by the sampled direction.
+        let ds = distance_to_center * cos_theta
+            - (self.radius * self.radius - distance_to_center * distance_to_center * sin_theta2)
+                .max(0.0)
+                .sqrt();
+        let cos_alpha = (distance_to_center * distance_to_center + self.radius * self.radius
+            - ds * ds)
+            / (2.0 * distance_to_center * self.radius);
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+
+        // Compute the point on the sphere from the angles alpha and phi.
+        let n =
+            -1.0 * (sin_alpha * phi.cos() * wc_x + sin_alpha * phi.sin() * wc_y + cos_alpha * wc);
+        let mut point = self.sampled_point(Point3::from_vec(self.radius * n));
+        point.set_time(reference.time());
+        Some((point, sampling::uniform_cone_pdf(cos_theta_max)))
+    }
+
+    fn pdf_solid_angle(&self, reference: &dyn Interaction, wi: Vector3<f32>) -> f32 {
+        let reference_object = self.world_to_object().transform(&reference.point());
+        let cos_theta_max = match self.cone_cos_theta_max(reference_object) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return pdf_solid_angle_by_area(self, reference, wi),
+        };
+
+        // Directions are sampled uniformly within the cone, so the density is
+        // constant for directions that hit the sphere.
+        if self.does_ray_intersect(&reference.spawn_ray(wi), false) {
+            sampling::uniform_cone_pdf(cos_theta_max)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::Sphere;
+    use crate::transform::Transform;
+    use cgmath::InnerSpace;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sample_area_covers_partial_sphere() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let sphere = Sphere::new(&object_to_world, false, 1.5, -0.5, 1.0, 270.0);
+        assert_area_sampling(&sphere, 0.75 * 2.0 * PI * 1.5 * 1.5);
+    }
+
+    #[test]
+    fn sample_solid_angle_samples_cone_from_outside() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let sphere = Sphere::new(&object_to_world, false, 1.5, -1.5, 1.5, 360.0);
+        let reference = Point3::new(0.5, 0.7, 4.0);
+        let distance2: f32 = (reference - Point3::new(0.3, -0.2, 0.1)).magnitude2();
+        let cos_theta_max = (1.0 - 1.5 * 1.5 / distance2).sqrt();
+        assert_solid_angle_sampling(&sphere, reference, 2.0 * PI * (1.0 - cos_theta_max));
+    }
+
+    #[test]
+    fn sample_solid_angle_falls_back_to_area_inside() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let sphere = Sphere::new(&object_to_world, false, 1.5, -1.5, 1.5, 360.0);
+        assert_solid_angle_sampling(&sphere, Point3::new(0.6, 0.1, 0.5), 4.0 * PI);
+    }
+
+    #[test]
+    fn sample_solid_angle_falls_back_to_area_when_clipped() {
+        // Every direction above the center of a hemisphere hits it once.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let sphere = Sphere::new(&object_to_world, false, 1.5, 0.0, 1.5, 360.0);
+        assert_solid_angle_sampling(&sphere, Point3::new(0.3, -0.2, 0.1), 2.0 * PI);
     }
 }
@@ -0,0 +1,611 @@
+use super::edge_key;
+use super::triangle::TiangleMeshBuilder;
+use super::triangle::TriangleMesh;
+use super::triangle::TriangleMeshError;
+use crate::transform;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// A control cage that is refined with Loop subdivision and tessellated into a
+/// triangle mesh on the limit surface. (p. 1069)
+///
+/// Edges on the boundary of the cage and edges marked as creases are kept
+/// sharp: they are subdivided with the boundary rules, so the limit surface
+/// follows a cubic B-spline curve along them instead of smoothing across them.
+/// Vertices where more than two sharp edges meet are corners and don't move.
+pub struct LoopSubdivisionSurface<'a> {
+    object_to_world: &'a transform::Transform,
+    reverse_orientation: bool,
+    levels: usize,
+    vertices: Vec<Point3<f32>>,
+    triangle_vertex_indices: Vec<(usize, usize, usize)>,
+    creases: Vec<(usize, usize)>,
+}
+
+/// An error that occurs when a `LoopSubdivisionSurface` is tessellated from an
+/// inconsistent control cage.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubdivisionSurfaceError {
+    /// A triangle refers to a vertex index that is outside the vertex array.
+    VertexIndexOutOfBounds {
+        triangle: usize,
+        vertex_index: usize,
+        vertex_count: usize,
+    },
+
+    /// A triangle refers to the same vertex more than once. Such triangles
+    /// have no well-defined edges, so they can't be subdivided.
+    DegenerateTriangle { triangle: usize },
+
+    /// A crease refers to a vertex index that is outside the vertex array.
+    CreaseVertexIndexOutOfBounds {
+        crease: usize,
+        vertex_index: usize,
+        vertex_count: usize,
+    },
+
+    /// The triangle mesh on the limit surface couldn't be built.
+    Mesh(TriangleMeshError),
+}
+
+impl std::fmt::Display for SubdivisionSurfaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubdivisionSurfaceError::VertexIndexOutOfBounds {
+                triangle,
+                vertex_index,
+                vertex_count,
+            } => write!(
+                f,
+                "triangle {} refers to vertex {} but the cage has only {} vertices",
+                triangle, vertex_index, vertex_count
+            ),
+            SubdivisionSurfaceError::DegenerateTriangle { triangle } => write!(
+                f,
+                "triangle {} refers to the same vertex more than once",
+                triangle
+            ),
+            SubdivisionSurfaceError::CreaseVertexIndexOutOfBounds {
+                crease,
+                vertex_index,
+                vertex_count,
+            } => write!(
+                f,
+                "crease {} refers to vertex {} but the cage has only {} vertices",
+                crease, vertex_index, vertex_count
+            ),
+            SubdivisionSurfaceError::Mesh(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SubdivisionSurfaceError {}
+
+impl<'a> LoopSubdivisionSurface<'a> {
+    /// Creates a subdivision surface from the object space vertices and
+    /// triangles of a control cage that will be subdivided `levels` times.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        levels: usize,
+        vertices: Vec<Point3<f32>>,
+        triangle_vertex_indices: Vec<(usize, usize, usize)>,
+    ) -> Self {
+        Self {
+            object_to_world,
+            reverse_orientation,
+            levels,
+            vertices,
+            triangle_vertex_indices,
+            creases: vec![],
+        }
+    }
+
+    /// Marks the cage edges between the given pairs of vertices as creases.
+    pub fn creases(mut self, creases: Vec<(usize, usize)>) -> Self {
+        self.creases = creases;
+        self
+    }
+
+    /// Subdivides the control cage, pushes the resulting vertices to their
+    /// positions on the limit surface, and builds a triangle mesh with the
+    /// limit surface's normals as per-vertex normals.
+    ///
+    /// Returns an error if any triangle or crease refers to a vertex that
+    /// doesn't exist, or if any triangle refers to the same vertex more than
+    /// once.
+    pub fn tessellate(self) -> Result<TriangleMesh<'a>, SubdivisionSurfaceError> {
+        let vertex_count = self.vertices.len();
+        for (triangle, &(i0, i1, i2)) in self.triangle_vertex_indices.iter().enumerate() {
+            for &vertex_index in [i0, i1, i2].iter() {
+                if vertex_index >= vertex_count {
+                    return Err(SubdivisionSurfaceError::VertexIndexOutOfBounds {
+                        triangle,
+                        vertex_index,
+                        vertex_count,
+                    });
+                }
+            }
+            if i0 == i1 || i1 == i2 || i2 == i0 {
+                return Err(SubdivisionSurfaceError::DegenerateTriangle { triangle });
+            }
+        }
+        for (crease, &(a, b)) in self.creases.iter().enumerate() {
+            for &vertex_index in [a, b].iter() {
+                if vertex_index >= vertex_count {
+                    return Err(SubdivisionSurfaceError::CreaseVertexIndexOutOfBounds {
+                        crease,
+                        vertex_index,
+                        vertex_count,
+                    });
+                }
+            }
+        }
+
+        let mut cage = ControlMesh {
+            vertices: self.vertices,
+            faces: self
+                .triangle_vertex_indices
+                .iter()
+                .map(|&(i0, i1, i2)| [i0, i1, i2])
+                .collect(),
+            creases: self.creases.iter().map(|&(a, b)| edge_key(a, b)).collect(),
+        };
+        for _ in 0..self.levels {
+            cage = cage.subdivide();
+        }
+
+        let topology = Topology::new(&cage);
+        let limit_positions = cage.limit_positions(&topology);
+        let limit_normals = cage.limit_normals(&topology);
+        let indices = cage.faces.iter().map(|f| (f[0], f[1], f[2])).collect();
+
+        TiangleMeshBuilder::new(
+            self.object_to_world,
+            self.reverse_orientation,
+            limit_positions,
+            indices,
+        )
+        .normals(limit_normals)
+        .build()
+        .map_err(SubdivisionSurfaceError::Mesh)
+    }
+}
+
+/// A triangle mesh at one level of subdivision.
+struct ControlMesh {
+    vertices: Vec<Point3<f32>>,
+
+    /// The vertex indices of each triangle, in counter-clockwise order.
+    faces: Vec<[usize; 3]>,
+
+    /// The edges that are kept sharp, keyed by `edge_key`.
+    creases: HashSet<(usize, usize)>,
+}
+
+/// Describes how a vertex is treated by the subdivision rules, based on the
+/// number of sharp edges that meet at it.
+#[derive(Clone, Copy)]
+enum VertexKind {
+    /// The vertex is smoothed using all of its neighbors. This includes
+    /// vertices with a single sharp edge, which form darts.
+    Smooth,
+
+    /// Exactly two sharp edges meet at the vertex, which is smoothed along
+    /// them using only the two given neighbors.
+    Crease(usize, usize),
+
+    /// More than two sharp edges meet at the vertex, or the vertex isn't part
+    /// of any face, so the vertex stays in place.
+    Corner,
+}
+
+/// The adjacency information for a `ControlMesh` that the subdivision rules
+/// need.
+struct Topology {
+    /// The faces that share each edge, keyed by `edge_key`.
+    edge_faces: HashMap<(usize, usize), Vec<usize>>,
+
+    /// The faces that contain each vertex.
+    vertex_faces: Vec<Vec<usize>>,
+
+    /// The distinct vertices that share an edge with each vertex.
+    neighbors: Vec<Vec<usize>>,
+
+    kinds: Vec<VertexKind>,
+}
+
+impl Topology {
+    fn new(mesh: &ControlMesh) -> Self {
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut vertex_faces = vec![vec![]; mesh.vertices.len()];
+        let mut neighbors = vec![vec![]; mesh.vertices.len()];
+        for (face_index, face) in mesh.faces.iter().enumerate() {
+            for i in 0..3 {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                edge_faces
+                    .entry(edge_key(a, b))
+                    .or_default()
+                    .push(face_index);
+                vertex_faces[a].push(face_index);
+                if !neighbors[a].contains(&b) {
+                    neighbors[a].push(b);
+                }
+                if !neighbors[b].contains(&a) {
+                    neighbors[b].push(a);
+                }
+            }
+        }
+
+        let kinds = (0..mesh.vertices.len())
+            .map(|v| {
+                if vertex_faces[v].is_empty() {
+                    return VertexKind::Corner;
+                }
+                let sharp_neighbors: Vec<usize> = neighbors[v]
+                    .iter()
+                    .copied()
+                    .filter(|&n| is_sharp(mesh, &edge_faces, v, n))
+                    .collect();
+                match sharp_neighbors.as_slice() {
+                    [] | [_] => VertexKind::Smooth,
+                    [n0, n1] => VertexKind::Crease(*n0, *n1),
+                    _ => VertexKind::Corner,
+                }
+            })
+            .collect();
+
+        Self {
+            edge_faces,
+            vertex_faces,
+            neighbors,
+            kinds,
+        }
+    }
+
+    /// Returns the neighbors of vertex `v` in counter-clockwise order around
+    /// it, along with a flag that indicates whether `v` lies on the boundary
+    /// of the mesh. For boundary vertices the ring starts and ends at the
+    /// vertex's two boundary neighbors. Returns `None` if the faces around the
+    /// vertex don't form a single fan.
+    fn ordered_one_ring(&self, mesh: &ControlMesh, v: usize) -> Option<(Vec<usize>, bool)> {
+        // Each face (v, a, b) links neighbor a to the next neighbor b.
+        let mut next = HashMap::new();
+        for &face_index in self.vertex_faces[v].iter() {
+            let face = mesh.faces[face_index];
+            let i = face.iter().position(|&fv| fv == v)?;
+            if next.insert(face[(i + 1) % 3], face[(i + 2) % 3]).is_some() {
+                return None;
+            }
+        }
+
+        // A boundary vertex's ring starts at the neighbor that no face links
+        // to.
+        let targets: HashSet<usize> = next.values().copied().collect();
+        let start = next.keys().copied().find(|a| !targets.contains(a));
+        let is_boundary = start.is_some();
+        let start = start.or_else(|| next.keys().copied().next())?;
+
+        let mut ring = vec![start];
+        let mut current = start;
+        while let Some(&following) = next.get(&current) {
+            if following == start {
+                break;
+            }
+            if ring.len() > next.len() {
+                return None;
+            }
+            ring.push(following);
+            current = following;
+        }
+
+        if ring.len() == self.neighbors[v].len() {
+            Some((ring, is_boundary))
+        } else {
+            None
+        }
+    }
+}
+
+impl ControlMesh {
+    /// Applies one level of Loop subdivision, splitting each triangle into
+    /// four.
+    fn subdivide(&self) -> ControlMesh {
+        let topology = Topology::new(self);
+
+        // Update the positions of the existing, even, vertices.
+        let mut vertices: Vec<Point3<f32>> = (0..self.vertices.len())
+            .map(|v| {
+                let p = self.vertices[v];
+                match topology.kinds[v] {
+                    VertexKind::Smooth => {
+                        let ring = &topology.neighbors[v];
+                        weight_one_ring(p, ring.iter().map(|&n| self.vertices[n]), beta(ring.len()))
+                    }
+                    VertexKind::Crease(n0, n1) => {
+                        weight_crease(p, self.vertices[n0], self.vertices[n1], 1.0 / 8.0)
+                    }
+                    VertexKind::Corner => p,
+                }
+            })
+            .collect();
+
+        // Compute the new, odd, vertices that split each edge.
+        let mut edge_vertices: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut edges: Vec<&(usize, usize)> = topology.edge_faces.keys().collect();
+        edges.sort();
+        for &&(a, b) in edges.iter() {
+            let pa = self.vertices[a].to_vec();
+            let pb = self.vertices[b].to_vec();
+            let p = if is_sharp(self, &topology.edge_faces, a, b) {
+                0.5 * (pa + pb)
+            } else {
+                // Weight the vertices opposite the edge in its two faces.
+                let faces = &topology.edge_faces[&(a, b)];
+                let opposite = |f: usize| {
+                    let face = self.faces[f];
+                    let c = face.iter().copied().find(|&v| v != a && v != b).unwrap();
+                    self.vertices[c].to_vec()
+                };
+                3.0 / 8.0 * (pa + pb) + 1.0 / 8.0 * (opposite(faces[0]) + opposite(faces[1]))
+            };
+            edge_vertices.insert((a, b), vertices.len());
+            vertices.push(Point3::from_vec(p));
+        }
+
+        // Split each face into four, keeping the original winding.
+        let edge_vertex = |a: usize, b: usize| edge_vertices[&edge_key(a, b)];
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|&[v0, v1, v2]| {
+                let e01 = edge_vertex(v0, v1);
+                let e12 = edge_vertex(v1, v2);
+                let e20 = edge_vertex(v2, v0);
+                vec![
+                    [v0, e01, e20],
+                    [v1, e12, e01],
+                    [v2, e20, e12],
+                    [e01, e12, e20],
+                ]
+            })
+            .collect();
+
+        // Each crease is split in two at its new vertex.
+        let creases = self
+            .creases
+            .iter()
+            .filter_map(|&(a, b)| edge_vertices.get(&(a, b)).map(|&m| (a, b, m)))
+            .flat_map(|(a, b, m)| vec![edge_key(a, m), edge_key(m, b)])
+            .collect();
+
+        ControlMesh {
+            vertices,
+            faces,
+            creases,
+        }
+    }
+
+    /// Returns the positions that the mesh's vertices converge to on the limit
+    /// surface.
+    fn limit_positions(&self, topology: &Topology) -> Vec<Point3<f32>> {
+        (0..self.vertices.len())
+            .map(|v| {
+                let p = self.vertices[v];
+                match topology.kinds[v] {
+                    VertexKind::Smooth => {
+                        let ring = &topology.neighbors[v];
+                        weight_one_ring(
+                            p,
+                            ring.iter().map(|&n| self.vertices[n]),
+                            loop_gamma(ring.len()),
+                        )
+                    }
+                    VertexKind::Crease(n0, n1) => {
+                        weight_crease(p, self.vertices[n0], self.vertices[n1], 1.0 / 5.0)
+                    }
+                    VertexKind::Corner => p,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the normals of the limit surface at the mesh's vertices.
+    ///
+    /// The normals are computed from the limit surface's tangents at smooth
+    /// interior vertices and at boundary vertices. There is no single normal
+    /// at corners or along interior creases, so the average of the adjacent
+    /// faces' normals is used there.
+    fn limit_normals(&self, topology: &Topology) -> Vec<Vector3<f32>> {
+        (0..self.vertices.len())
+            .map(|v| {
+                let face_normal = self.average_face_normal(topology, v);
+                let p = self.vertices[v].to_vec();
+                let ring = topology.ordered_one_ring(self, v);
+                let n = match (topology.kinds[v], ring) {
+                    (VertexKind::Smooth, Some((ring, false))) => {
+                        // Compute tangents of interior vertex.
+                        let valence = ring.len();
+                        let mut s = Vector3::new(0.0, 0.0, 0.0);
+                        let mut t = Vector3::new(0.0, 0.0, 0.0);
+                        for (j, &n) in ring.iter().enumerate() {
+                            let angle = 2.0 * std::f32::consts::PI * j as f32 / valence as f32;
+                            s += angle.cos() * self.vertices[n].to_vec();
+                            t += angle.sin() * self.vertices[n].to_vec();
+                        }
+                        s.cross(t)
+                    }
+                    (VertexKind::Crease(_, _), Some((ring, true))) => {
+                        // Compute tangents of boundary vertex.
+                        let ring: Vec<Vector3<f32>> =
+                            ring.iter().map(|&n| self.vertices[n].to_vec()).collect();
+                        let valence = ring.len();
+                        let s = ring[valence - 1] - ring[0];
+                        let t = match valence {
+                            2 => ring[0] + ring[1] - 2.0 * p,
+                            3 => ring[1] - p,
+                            4 => -1.0 * ring[0] + 2.0 * ring[1] + 2.0 * ring[2] - ring[3] - 2.0 * p,
+                            _ => {
+                                let theta = std::f32::consts::PI / (valence - 1) as f32;
+                                let mut t = theta.sin() * (ring[0] + ring[valence - 1]);
+                                for (k, &r) in ring.iter().enumerate().take(valence - 1).skip(1) {
+                                    let weight =
+                                        (2.0 * theta.cos() - 2.0) * (k as f32 * theta).sin();
+                                    t += weight * r;
+                                }
+                                -1.0 * t
+                            }
+                        };
+                        t.cross(s)
+                    }
+                    _ => face_normal,
+                };
+
+                // Keep the normal on the same side of the surface as the
+                // faces around the vertex.
+                if n.magnitude2() == 0.0 {
+                    face_normal
+                } else if n.dot(face_normal) < 0.0 {
+                    -1.0 * n.normalize()
+                } else {
+                    n.normalize()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the normalized average of the normals of the faces around the
+    /// vertex `v`, weighted by the faces' areas.
+    fn average_face_normal(&self, topology: &Topology, v: usize) -> Vector3<f32> {
+        let n = topology.vertex_faces[v]
+            .iter()
+            .map(|&f| {
+                let [v0, v1, v2] = self.faces[f];
+                (self.vertices[v1] - self.vertices[v0]).cross(self.vertices[v2] - self.vertices[v0])
+            })
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+}
+
+/// Returns a flag that indicates whether the edge between `a` and `b` is kept
+/// sharp, either because it is on the mesh's boundary or because it is marked
+/// as a crease.
+fn is_sharp(
+    mesh: &ControlMesh,
+    edge_faces: &HashMap<(usize, usize), Vec<usize>>,
+    a: usize,
+    b: usize,
+) -> bool {
+    let key = edge_key(a, b);
+    mesh.creases.contains(&key) || edge_faces.get(&key).is_none_or(|faces| faces.len() != 2)
+}
+
+/// Returns the weight given to each neighbor of a smooth vertex with the
+/// given valence when it is subdivided.
+fn beta(valence: usize) -> f32 {
+    if valence == 3 {
+        3.0 / 16.0
+    } else {
+        3.0 / (8.0 * valence as f32)
+    }
+}
+
+/// Returns the weight given to each neighbor of a smooth vertex with the
+/// given valence when it is pushed to the limit surface.
+fn loop_gamma(valence: usize) -> f32 {
+    1.0 / (valence as f32 + 3.0 / (8.0 * beta(valence)))
+}
+
+/// Returns the weighted average of a vertex and its neighbors in which each
+/// neighbor has weight `beta`.
+fn weight_one_ring<I: ExactSizeIterator<Item = Point3<f32>>>(
+    p: Point3<f32>,
+    ring: I,
+    beta: f32,
+) -> Point3<f32> {
+    let valence = ring.len() as f32;
+    let ring_sum = ring.fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n.to_vec());
+    Point3::from_vec((1.0 - valence * beta) * p.to_vec() + beta * ring_sum)
+}
+
+/// Returns the weighted average of a vertex and its two neighbors along sharp
+/// edges in which each neighbor has weight `beta`.
+fn weight_crease(p: Point3<f32>, n0: Point3<f32>, n1: Point3<f32>, beta: f32) -> Point3<f32> {
+    Point3::from_vec((1.0 - 2.0 * beta) * p.to_vec() + beta * (n0.to_vec() + n1.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoopSubdivisionSurface;
+    use super::SubdivisionSurfaceError;
+    use crate::transform::Transform;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    /// Returns the vertices of a tetrahedron.
+    fn tetrahedron_vertices() -> Vec<Point3<f32>> {
+        vec![
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(-1.0, -1.0, 1.0),
+            Point3::new(-1.0, 1.0, -1.0),
+            Point3::new(1.0, -1.0, -1.0),
+        ]
+    }
+
+    #[test]
+    fn tessellate_accepts_closed_cage() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let surface = LoopSubdivisionSurface::new(
+            &object_to_world,
+            false,
+            2,
+            tetrahedron_vertices(),
+            vec![(0, 1, 2), (0, 3, 1), (0, 2, 3), (1, 3, 2)],
+        )
+        .creases(vec![(0, 1)]);
+        assert!(surface.tessellate().is_ok());
+    }
+
+    #[test]
+    fn tessellate_rejects_degenerate_triangle() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let surface = LoopSubdivisionSurface::new(
+            &object_to_world,
+            false,
+            1,
+            tetrahedron_vertices(),
+            vec![(0, 1, 2), (0, 0, 1)],
+        );
+        assert_eq!(
+            surface.tessellate().err(),
+            Some(SubdivisionSurfaceError::DegenerateTriangle { triangle: 1 })
+        );
+    }
+
+    #[test]
+    fn tessellate_rejects_crease_with_missing_vertex() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let surface = LoopSubdivisionSurface::new(
+            &object_to_world,
+            false,
+            1,
+            tetrahedron_vertices(),
+            vec![(0, 1, 2)],
+        )
+        .creases(vec![(0, 1), (2, 7)]);
+        assert_eq!(
+            surface.tessellate().err(),
+            Some(SubdivisionSurfaceError::CreaseVertexIndexOutOfBounds {
+                crease: 1,
+                vertex_index: 7,
+                vertex_count: 4,
+            })
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use super::azimuth;
+use super::weingarten;
+use super::Shape;
+use crate::bounding_box;
+use crate::interaction::SurfaceInteraction;
+use crate::math;
+use crate::ray::Ray;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use cgmath::InnerSpace;
+use cgmath::Point3;
+use cgmath::Vector3;
+
+/// The factor by which the torus's bounding sphere is enlarged before it is
+/// used to restrict the range searched for quartic roots. This keeps hits on
+/// the outer equator from falling just outside the range due to rounding.
+const BOUNDING_SPHERE_PADDING: f64 = 1.01;
+
+/// A torus centered at the origin in object space, with the z axis running
+/// through its hole.
+///
+/// A point on the torus's surface is parameterized by `phi`, the angle around
+/// the z axis, and `theta`, the angle around the tube. `theta` is zero on the
+/// torus's outer equator and increases towards the positive z axis.
+pub struct Torus<'a> {
+    // Generic shape fields
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
+    reverse_orientation: bool,
+
+    // Torus-specific fields
+    major_radius: f32,
+    minor_radius: f32,
+    theta_min: f32,
+    theta_max: f32,
+    phi_max: f32,
+}
+
+impl<'a> Torus<'a> {
+    /// Creates a torus whose tube of radius `minor_radius` is centered on a
+    /// circle of radius `major_radius` in the z = 0 plane. The tube is swept
+    /// from `theta_min` to `theta_max` degrees around itself and up to
+    /// `phi_max` degrees around the z axis.
+    pub fn new(
+        object_to_world: &'a transform::Transform,
+        reverse_orientation: bool,
+        major_radius: f32,
+        minor_radius: f32,
+        theta_min: f32,
+        theta_max: f32,
+        phi_max: f32,
+    ) -> Self {
+        let theta0 = math::clamp(theta_min, 0.0, 360.0);
+        let theta1 = math::clamp(theta_max, 0.0, 360.0);
+        Self {
+            // Generic shape fields
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            // Torus-specific fields
+            major_radius,
+            minor_radius,
+            theta_min: theta0.min(theta1).to_radians(),
+            theta_max: theta0.max(theta1).to_radians(),
+            phi_max: math::clamp(phi_max, 0.0, 360.0).to_radians(),
+        }
+    }
+
+    /// Returns the parametric values of the intersections between the object
+    /// space ray and the full, unclipped torus in the (0, `ray.t_max`) range,
+    /// in increasing order.
+    fn unclipped_hit_ts(&self, ray: &Ray) -> Vec<f32> {
+        let major_radius = self.major_radius as f64;
+        let minor_radius = self.minor_radius as f64;
+        let o = Vector3::new(
+            ray.origin.x as f64,
+            ray.origin.y as f64,
+            ray.origin.z as f64,
+        );
+        let d = Vector3::new(
+            ray.direction.x as f64,
+            ray.direction.y as f64,
+            ray.direction.z as f64,
+        );
+
+        // Restrict the search for roots to the part of the ray inside the
+        // torus's bounding sphere. Moving the ray's origin to the start of
+        // that range also keeps the quartic's coefficients well conditioned
+        // for rays that start far from the torus.
+        let sphere_radius = (major_radius + minor_radius) * BOUNDING_SPHERE_PADDING;
+        let a = d.dot(d);
+        let b = 2.0 * o.dot(d);
+        let c = o.dot(o) - sphere_radius * sphere_radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return vec![];
+        }
+        let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+        let (t0, t1) = (q / a, c / q);
+        let t_enter = t0.min(t1).max(0.0);
+        let t_exit = t0.max(t1).min(ray.t_max as f64);
+        if t_enter >= t_exit {
+            return vec![];
+        }
+        let o = o + t_enter * d;
+
+        // Compute quartic torus coefficients by substituting the ray into the
+        // torus's implicit equation,
+        // (x^2 + y^2 + z^2 + R^2 - r^2)^2 - 4 R^2 (x^2 + y^2) = 0.
+        let four_major_radius2 = 4.0 * major_radius * major_radius;
+        let dd = d.dot(d);
+        let od = o.dot(d);
+        let k = o.dot(o) + major_radius * major_radius - minor_radius * minor_radius;
+        let dxy = d.x * d.x + d.y * d.y;
+        let odxy = o.x * d.x + o.y * d.y;
+        let oxy = o.x * o.x + o.y * o.y;
+        let coefficients = [
+            k * k - four_major_radius2 * oxy,
+            4.0 * od * k - 2.0 * four_major_radius2 * odxy,
+            4.0 * od * od + 2.0 * dd * k - four_major_radius2 * dxy,
+            4.0 * dd * od,
+            dd * dd,
+        ];
+
+        math::polynomial_roots(&coefficients, 0.0, t_exit - t_enter)
+            .into_iter()
+            .map(|t| (t + t_enter) as f32)
+            .filter(|&t| t > 0.0 && t < ray.t_max)
+            .collect()
+    }
+
+    /// Returns the object space point on the torus's surface that is hit by
+    /// the ray at parametric value `t`, along with the point's `phi` and
+    /// `theta` values. Returns `None` if the point has been clipped away.
+    fn unclipped_hit_point_and_angles(&self, ray: &Ray, t: f32) -> Option<(Point3<f32>, f32, f32)> {
+        let p = ray.at_t(t);
+
+        // Refine the torus intersection point by reprojecting it onto the
+        // surface of the tube around the nearest point on the center circle.
+        let hit_radius = (p.x * p.x + p.y * p.y).sqrt();
+        if hit_radius == 0.0 {
+            return None;
+        }
+        let center = Vector3::new(
+            self.major_radius * p.x / hit_radius,
+            self.major_radius * p.y / hit_radius,
+            0.0,
+        );
+        let offset = Vector3::new(p.x - center.x, p.y - center.y, p.z);
+        let offset_length =
+            (offset.x * offset.x + offset.y * offset.y + offset.z * offset.z).sqrt();
+        if offset_length == 0.0 {
+            return None;
+        }
+        let p = Point3::new(
+            center.x + offset.x * self.minor_radius / offset_length,
+            center.y + offset.y * self.minor_radius / offset_length,
+            offset.z * self.minor_radius / offset_length,
+        );
+
+        let phi = azimuth(&p);
+        let mut theta = p.z.atan2(hit_radius - self.major_radius);
+        if theta < 0.0 {
+            theta += 2.0 * std::f32::consts::PI;
+        }
+
+        if theta < self.theta_min || theta > self.theta_max || phi > self.phi_max {
+            None
+        } else {
+            Some((p, phi, theta))
+        }
+    }
+}
+
+impl<'a> Shape for Torus<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.object_to_world
+    }
+
+    fn object_to_world_swaps_handedness(&self) -> bool {
+        self.object_to_world_swaps_handedness
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn object_bound(&self) -> bounding_box::Bounds3<f32> {
+        let outer_radius = self.major_radius + self.minor_radius;
+        bounding_box::Bounds3::from_corners(
+            cgmath::Point3::new(
+                -1.0 * outer_radius,
+                -1.0 * outer_radius,
+                -1.0 * self.minor_radius,
+            ),
+            cgmath::Point3::new(outer_radius, outer_radius, self.minor_radius),
+        )
+    }
+
+    fn world_bound(&self) -> bounding_box::Bounds3<f32> {
+        self.object_to_world().transform(&self.object_bound())
+    }
+
+    fn ray_intersection(
+        &self,
+        ray: &crate::ray::Ray,
+        _test_alpha_texture: bool,
+    ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
+        let ray = self.world_to_object().transform(ray);
+
+        // Find the nearest hit that hasn't been clipped away.
+        let (t_shape_hit, (p_hit, phi, theta)) =
+            self.unclipped_hit_ts(&ray).into_iter().find_map(|t| {
+                self.unclipped_hit_point_and_angles(&ray, t)
+                    .map(|hit| (t, hit))
+            })?;
+
+        // Find parametric representation of torus hit.
+        let theta_range = self.theta_max - self.theta_min;
+        let u = phi / self.phi_max;
+        let v = (theta - self.theta_min) / theta_range;
+
+        // Compute torus dpdu and dpdv.
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let r = self.minor_radius;
+        let dpdu = Vector3::new(-1.0 * self.phi_max * p_hit.y, self.phi_max * p_hit.x, 0.0);
+        let dpdv = theta_range
+            * Vector3::new(
+                -1.0 * r * sin_theta * cos_phi,
+                -1.0 * r * sin_theta * sin_phi,
+                r * cos_theta,
+            );
+
+        // Compute torus dndu and dndv.
+        let d2pduu = -1.0 * self.phi_max * self.phi_max * Vector3::new(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = self.phi_max
+            * theta_range
+            * Vector3::new(r * sin_theta * sin_phi, -1.0 * r * sin_theta * cos_phi, 0.0);
+        let d2pdvv = -1.0
+            * theta_range
+            * theta_range
+            * Vector3::new(
+                r * cos_theta * cos_phi,
+                r * cos_theta * sin_phi,
+                r * sin_theta,
+            );
+        let (dndu, dndv) = weingarten(dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        // Compute error bounds for torus intersection. The reprojected point
+        // is built from the center circle and the tube's radius, so the
+        // error in each dimension is bounded relative to both the point's
+        // coordinate and the tube's radius.
+        let p_error =
+            math::gamma(8) * Vector3::new(p_hit.x.abs() + r, p_hit.y.abs() + r, p_hit.z.abs() + r);
+
+        // Initialize SurfaceInteraction from parametric information.
+        let interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            cgmath::Point2::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        );
+
+        Some((t_shape_hit, self.object_to_world.transform(&interaction)))
+    }
+
+    fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
+        self.ray_intersection(ray, test_alpha_texture).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.phi_max
+            * self.minor_radius
+            * (self.major_radius * (self.theta_max - self.theta_min)
+                + self.minor_radius * (self.theta_max.sin() - self.theta_min.sin()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Torus;
+    use crate::interaction::Interaction;
+    use crate::ray::Ray;
+    use crate::shape::Shape;
+    use crate::transform::Transform;
+    use cgmath::InnerSpace;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    /// The offset of the torus's center from the world space origin.
+    fn center() -> Vector3<f32> {
+        Vector3::new(0.3, -0.2, 0.1)
+    }
+
+    /// Returns the ray from `origin` in `direction`, both given relative to the
+    /// torus's center.
+    fn ray(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin: origin + center(),
+            direction,
+            t_max: f32::INFINITY,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the distance from the point, given relative to the torus's
+    /// center, to the surface of a torus with major radius 2 and minor radius
+    /// 0.5.
+    fn distance_to_surface(p: Point3<f32>) -> f32 {
+        let hit_radius = (p.x * p.x + p.y * p.y).sqrt();
+        ((hit_radius - 2.0) * (hit_radius - 2.0) + p.z * p.z).sqrt() - 0.5
+    }
+
+    #[test]
+    fn ray_intersection_finds_known_hits() {
+        let object_to_world = Transform::translate(center());
+        let torus = Torus::new(&object_to_world, false, 2.0, 0.5, 0.0, 360.0, 360.0);
+        let cases = [
+            // Through the tube's center circle from outside.
+            (
+                Point3::new(-5.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                2.5,
+            ),
+            // From the middle of the hole towards its inner equator.
+            (Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 1.5),
+            // Down onto the top of the tube.
+            (
+                Point3::new(0.0, 2.0, 5.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                4.5,
+            ),
+        ];
+        for &(origin, direction, expected_t) in cases.iter() {
+            let (t, _) = torus
+                .ray_intersection(&ray(origin, direction), false)
+                .unwrap();
+            assert!(
+                (t - expected_t).abs() < 1e-4,
+                "t is {} but expected {}",
+                t,
+                expected_t
+            );
+        }
+    }
+
+    #[test]
+    fn ray_intersection_misses() {
+        let object_to_world = Transform::translate(center());
+        let torus = Torus::new(&object_to_world, false, 2.0, 0.5, 0.0, 360.0, 360.0);
+        let cases = [
+            // Straight through the hole.
+            (Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0)),
+            // Through the hole at an angle.
+            (Point3::new(-1.0, 0.5, -5.0), Vector3::new(0.2, -0.1, 1.0)),
+            // Over the top of the torus.
+            (Point3::new(-5.0, 0.0, 0.6), Vector3::new(1.0, 0.0, 0.0)),
+            // Away from the torus.
+            (Point3::new(-5.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0)),
+        ];
+        for &(origin, direction) in cases.iter() {
+            assert!(!torus.does_ray_intersect(&ray(origin, direction), false));
+        }
+    }
+
+    #[test]
+    fn ray_intersection_handles_near_tangent_rays() {
+        // Rays that just graze the outer equator must hit it, and rays that
+        // just pass it must not.
+        let object_to_world = Transform::translate(center());
+        let torus = Torus::new(&object_to_world, false, 2.0, 0.5, 0.0, 360.0, 360.0);
+        for i in 1..=100 {
+            let offset = i as f32 * 1e-4;
+            let grazing = ray(
+                Point3::new(2.5 - offset, -5.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+            );
+            let (t, interaction) = torus.ray_intersection(&grazing, false).unwrap();
+            let expected_t = 5.0 - (2.5f32 * 2.5 - (2.5 - offset) * (2.5 - offset)).sqrt();
+            assert!(
+                (t - expected_t).abs() < 1e-3,
+                "t is {} but expected {}",
+                t,
+                expected_t
+            );
+            assert!(distance_to_surface(interaction.point() - center()).abs() < 1e-4);
+
+            let passing = ray(
+                Point3::new(2.5 + offset, -5.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+            );
+            assert!(!torus.does_ray_intersect(&passing, false));
+        }
+    }
+
+    #[test]
+    fn spawned_rays_do_not_hit_the_same_surface() {
+        // Rays are traced down onto the torus from a grid of origins above it,
+        // and rays are spawned from each hit back into the side of the surface
+        // that the hit came from. Any hit that those rays find must be on a
+        // different part of the torus.
+        let object_to_world = Transform::translate(center());
+        let torus = Torus::new(&object_to_world, false, 2.0, 0.5, 0.0, 360.0, 360.0);
+        let n = 64;
+        let mut hit_count = 0;
+        for i in 0..n * n {
+            let x = -3.0 + 6.0 * ((i % n) as f32 + 0.5) / n as f32;
+            let y = -3.0 + 6.0 * ((i / n) as f32 + 0.5) / n as f32;
+            let incoming = ray(Point3::new(x, y, 3.0), Vector3::new(0.3, 0.2, -1.0));
+            let interaction = match torus.ray_intersection(&incoming, false) {
+                Some((_, interaction)) => interaction,
+                None => continue,
+            };
+            hit_count += 1;
+            assert!(distance_to_surface(interaction.point() - center()).abs() < 1e-4);
+
+            let normal = interaction.normal().unwrap();
+            let normal = if normal.dot(incoming.direction) > 0.0 {
+                -1.0 * normal
+            } else {
+                normal
+            };
+            for &direction in [normal, normal + 0.5 * incoming.direction.normalize()].iter() {
+                let spawned = interaction.spawn_ray(direction);
+                if let Some((_, rehit)) = torus.ray_intersection(&spawned, false) {
+                    let distance = (rehit.point() - interaction.point()).magnitude();
+                    assert!(distance > 1e-2, "re-hit at distance {}", distance);
+                }
+            }
+        }
+        assert!(hit_count > n * n / 4);
+    }
+}
@@ -1,11 +1,34 @@
+use super::displacement::DisplacedMesh;
+use super::displacement::Displacement;
+use super::passes_alpha_test;
+use super::pdf_solid_angle_by_area;
+use super::sample_solid_angle_by_area;
 use super::Shape;
 use crate::bounding_box::Bounds3;
-use cgmath::{Matrix4, Point2, Point3, Transform, Vector3};
+use crate::interaction::Interaction;
+use crate::interaction::SurfaceInteraction;
+use crate::interaction::SurfacePoint;
+use crate::math;
+use crate::sampling;
+use crate::texture::Texture;
+use crate::transform;
+use crate::transform::ApplyTransform;
+use crate::transform::SwapHandedness;
+use crate::vector;
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector3};
+
+/// The smallest solid angle, in steradians, that a triangle must subtend from a
+/// reference point to be sampled by solid angle rather than by area.
+const MIN_SPHERICAL_SAMPLE_AREA: f32 = 3e-4;
+
+/// The largest solid angle, in steradians, that a triangle may subtend from a
+/// reference point to be sampled by solid angle rather than by area.
+const MAX_SPHERICAL_SAMPLE_AREA: f32 = 6.22;
 
 /// A mesh of triangles.
 pub struct TriangleMesh<'a> {
-    object_to_world: &'a Matrix4<f32>,
-    world_to_object: &'a cgmath::Matrix4<f32>,
+    object_to_world: &'a transform::Transform,
+    object_to_world_swaps_handedness: bool,
     reverse_orientation: bool,
 
     /// The vertices in world space that make up the mesh.
@@ -23,6 +46,18 @@ pub struct TriangleMesh<'a> {
 
     /// An array containing a UV coordinate for each vertex in the mesh.
     uvs: Option<Vec<Point2<f32>>>,
+
+    /// A distribution over the triangles in the mesh that is proportional to
+    /// their areas.
+    area_distribution: sampling::Distribution1D,
+
+    /// A texture that cuts holes in the mesh where its value is zero, and
+    /// makes the mesh partially transparent where it is fractional.
+    alpha: Option<&'a dyn Texture<f32>>,
+
+    /// A texture that is tested in addition to `alpha` for rays that only test
+    /// for occlusion, such as shadow rays.
+    shadow_alpha: Option<&'a dyn Texture<f32>>,
 }
 
 /// A reference to an individual triangle in a mesh.
@@ -33,11 +68,13 @@ pub struct Triangle<'a> {
 
 impl<'a> Triangle<'a> {
     pub fn object_space_vertices(&self) -> (Point3<f32>, Point3<f32>, Point3<f32>) {
-        let (i0, i1, i2) = self.mesh.triangle_vertex_indices[self.index_in_mesh];
-        let p0 = self.world_to_object().transform_point(self.mesh.world_space_vertices[i0]);
-        let p1 = self.world_to_object().transform_point(self.mesh.world_space_vertices[i1]);
-        let p2 = self.world_to_object().transform_point(self.mesh.world_space_vertices[i2]);
-        (p0, p1, p2)
+        let world_to_object = self.world_to_object();
+        let (p0, p1, p2) = self.world_space_vertices();
+        (
+            world_to_object.transform(&p0),
+            world_to_object.transform(&p1),
+            world_to_object.transform(&p2),
+        )
     }
 
     pub fn world_space_vertices(&self) -> (Point3<f32>, Point3<f32>, Point3<f32>) {
@@ -47,6 +84,141 @@ impl<'a> Triangle<'a> {
         let p2 = self.mesh.world_space_vertices[i2];
         (p0, p1, p2)
     }
+
+    /// Returns the UV coordinates at the triangle's vertices. If the mesh does
+    /// not have UV coordinates then default coordinates are returned.
+    pub fn uvs(&self) -> (Point2<f32>, Point2<f32>, Point2<f32>) {
+        match &self.mesh.uvs {
+            Some(uvs) => {
+                let (i0, i1, i2) = self.mesh.triangle_vertex_indices[self.index_in_mesh];
+                (uvs[i0], uvs[i1], uvs[i2])
+            }
+            None => (
+                Point2::new(0.0, 0.0),
+                Point2::new(1.0, 0.0),
+                Point2::new(1.0, 1.0),
+            ),
+        }
+    }
+
+    /// Returns the point on the triangle with the given barycentric
+    /// coordinates. Its normal is the triangle's geometric normal, flipped to
+    /// lie in the same hemisphere as the interpolated per-vertex normal if the
+    /// mesh has per-vertex normals.
+    fn sampled_point(&self, b: (f32, f32, f32)) -> SurfacePoint {
+        let (p0, p1, p2) = self.world_space_vertices();
+        let p = Point3::from_vec(b.0 * p0.to_vec() + b.1 * p1.to_vec() + b.2 * p2.to_vec());
+        let p_abs_sum = (b.0 * p0.to_vec()).map(|f| f.abs())
+            + (b.1 * p1.to_vec()).map(|f| f.abs())
+            + (b.2 * p2.to_vec()).map(|f| f.abs());
+        let p_error = math::gamma(6) * p_abs_sum;
+
+        let n = (p0 - p2).cross(p1 - p2).normalize();
+        let (i0, i1, i2) = self.mesh.triangle_vertex_indices[self.index_in_mesh];
+        let ns = self
+            .mesh
+            .normals
+            .as_ref()
+            .map(|normals| b.0 * normals[i0] + b.1 * normals[i1] + b.2 * normals[i2]);
+        let n = match ns {
+            Some(ns) if ns.magnitude2() > 0.0 => vector::face_forward(n, ns),
+            _ if self.reverse_orientation() ^ self.object_to_world_swaps_handedness() => -1.0 * n,
+            _ => n,
+        };
+
+        let (uv0, uv1, uv2) = self.uvs();
+        let uv = Point2::from_vec(b.0 * uv0.to_vec() + b.1 * uv1.to_vec() + b.2 * uv2.to_vec());
+        SurfacePoint::new(p, p_error, n, uv)
+    }
+
+    /// Returns the solid angle that the triangle subtends from the point `p`.
+    fn solid_angle(&self, p: Point3<f32>) -> f32 {
+        let (p0, p1, p2) = self.world_space_vertices();
+        sampling::spherical_triangle_area(
+            (p0 - p).normalize(),
+            (p1 - p).normalize(),
+            (p2 - p).normalize(),
+        )
+    }
+
+    /// Returns the shading geometry partial derivatives at the point on the
+    /// triangle with the given barycentric coordinates, interpolated from the
+    /// mesh's per-vertex normals and tangents. (p. 166)
+    fn shading_geometry(
+        &self,
+        b: (f32, f32, f32),
+        duv02: cgmath::Vector2<f32>,
+        duv12: cgmath::Vector2<f32>,
+        geometric_normal: Vector3<f32>,
+        dpdu: Vector3<f32>,
+    ) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let (i0, i1, i2) = self.mesh.triangle_vertex_indices[self.index_in_mesh];
+
+        // Compute shading normal for triangle.
+        let ns = match &self.mesh.normals {
+            Some(normals) => {
+                let ns = b.0 * normals[i0] + b.1 * normals[i1] + b.2 * normals[i2];
+                if ns.magnitude2() > 0.0 {
+                    ns.normalize()
+                } else {
+                    geometric_normal
+                }
+            }
+            None => geometric_normal,
+        };
+
+        // Compute shading tangent for triangle.
+        let ss = match &self.mesh.tangents {
+            Some(tangents) => {
+                let ss = b.0 * tangents[i0] + b.1 * tangents[i1] + b.2 * tangents[i2];
+                if ss.magnitude2() > 0.0 {
+                    ss.normalize()
+                } else {
+                    dpdu.normalize()
+                }
+            }
+            None => dpdu.normalize(),
+        };
+
+        // Compute shading bitangent for triangle and adjust the shading
+        // tangent so that the shading geometry is orthogonal.
+        let ts = ss.cross(ns);
+        let (ss, ts) = if ts.magnitude2() > 0.0 {
+            let ts = ts.normalize();
+            (ts.cross(ns), ts)
+        } else {
+            vector::coordinate_system(ns)
+        };
+
+        // Compute dndu and dndv for triangle shading geometry.
+        let (dndu, dndv) = match &self.mesh.normals {
+            Some(normals) => {
+                let dn1 = normals[i0] - normals[i2];
+                let dn2 = normals[i1] - normals[i2];
+                let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+                if determinant.abs() < 1e-8 {
+                    // Handle the degenerate UV case by choosing an arbitrary
+                    // coordinate system about the change in normal across the
+                    // triangle, as pbrt does.
+                    let dn = (normals[i2] - normals[i0]).cross(normals[i1] - normals[i0]);
+                    if dn.magnitude2() == 0.0 {
+                        (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0))
+                    } else {
+                        vector::coordinate_system(dn)
+                    }
+                } else {
+                    let inv_det = 1.0 / determinant;
+                    (
+                        (duv12.y * dn1 - duv02.y * dn2) * inv_det,
+                        (duv02.x * dn2 - duv12.x * dn1) * inv_det,
+                    )
+                }
+            }
+            None => (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        };
+
+        (ss, ts, dndu, dndv)
+    }
 }
 
 impl<'a> TriangleMesh<'a> {
@@ -56,36 +228,113 @@ impl<'a> TriangleMesh<'a> {
             index_in_mesh: index,
         }
     }
+
+    /// Returns the number of triangles in the mesh.
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_vertex_indices.len()
+    }
+
+    /// Returns an iterator over references to each triangle in the mesh.
+    pub fn triangles(&'a self) -> impl Iterator<Item = Triangle<'a>> {
+        (0..self.triangle_count()).map(move |i| self.triangle_at(i))
+    }
+
+    /// Returns the total surface area of the triangles in the mesh.
+    pub fn surface_area(&self) -> f32 {
+        self.area_distribution.func_int() * self.triangle_count() as f32
+    }
+
+    /// Picks a triangle with probability proportional to its area, so that
+    /// sampling the picked triangle by area samples the whole mesh uniformly
+    /// by area. Returns the triangle, the probability of picking it, and `u`
+    /// remapped to a new uniform sample. Returns `None` if the mesh has no
+    /// triangles.
+    pub fn sample_triangle(&'a self, u: f32) -> Option<(Triangle<'a>, f32, f32)> {
+        if self.triangle_count() == 0 {
+            return None;
+        }
+        let (index, pmf, u_remapped) = self.area_distribution.sample_discrete(u);
+        Some((self.triangle_at(index), pmf, u_remapped))
+    }
 }
 
+/// An error that occurs when a `TriangleMesh` is built from inconsistent data.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TriangleMeshError {
+    /// A triangle refers to a vertex index that is outside the vertex array.
+    VertexIndexOutOfBounds {
+        triangle: usize,
+        vertex_index: usize,
+        vertex_count: usize,
+    },
+
+    /// A per-vertex attribute array does not contain exactly one element for
+    /// each vertex in the mesh.
+    AttributeLengthMismatch {
+        attribute: &'static str,
+        attribute_count: usize,
+        vertex_count: usize,
+    },
+}
+
+impl std::fmt::Display for TriangleMeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriangleMeshError::VertexIndexOutOfBounds {
+                triangle,
+                vertex_index,
+                vertex_count,
+            } => write!(
+                f,
+                "triangle {} refers to vertex {} but the mesh has only {} vertices",
+                triangle, vertex_index, vertex_count
+            ),
+            TriangleMeshError::AttributeLengthMismatch {
+                attribute,
+                attribute_count,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh has {} {} but {} vertices",
+                attribute_count, attribute, vertex_count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TriangleMeshError {}
+
 pub struct TiangleMeshBuilder<'a> {
-    object_to_world: &'a Matrix4<f32>,
-    world_to_object: &'a cgmath::Matrix4<f32>,
+    object_to_world: &'a transform::Transform,
     reverse_orientation: bool,
     object_space_vertices: Vec<Point3<f32>>,
     triangle_vertex_indices: Vec<(usize, usize, usize)>,
     tangents: Option<Vec<Vector3<f32>>>,
     normals: Option<Vec<Vector3<f32>>>,
     uvs: Option<Vec<Point2<f32>>>,
+    displacement: Option<Displacement<'a>>,
+    alpha: Option<&'a dyn Texture<f32>>,
+    shadow_alpha: Option<&'a dyn Texture<f32>>,
 }
 
 impl<'a> TiangleMeshBuilder<'a> {
     pub fn new(
-        object_to_world: &'a Matrix4<f32>,
-        world_to_object: &'a cgmath::Matrix4<f32>,
+        object_to_world: &'a transform::Transform,
         reverse_orientation: bool,
         object_space_vertices: Vec<Point3<f32>>,
         triangle_vertex_indices: Vec<(usize, usize, usize)>,
     ) -> Self {
         Self {
             object_to_world,
-            world_to_object,
             reverse_orientation,
             object_space_vertices,
             triangle_vertex_indices,
             tangents: None,
             normals: None,
             uvs: None,
+            displacement: None,
+            alpha: None,
+            shadow_alpha: None,
         }
     }
 
@@ -103,19 +352,153 @@ impl<'a> TiangleMeshBuilder<'a> {
         self.uvs = Some(uvs);
         self
     }
+
+    /// Tessellates and displaces the mesh when it is built. The built mesh's
+    /// normals are recomputed from the displaced surface, replacing any
+    /// normals given to the builder.
+    pub fn displacement(mut self, displacement: Displacement<'a>) -> Self {
+        self.displacement = Some(displacement);
+        self
+    }
+
+    /// Cuts holes in the mesh where `alpha` is zero. Where it is fractional,
+    /// rays pass through the mesh with probability one minus alpha.
+    pub fn alpha(mut self, alpha: &'a dyn Texture<f32>) -> Self {
+        self.alpha = Some(alpha);
+        self
+    }
+
+    /// Also tests `shadow_alpha`, on top of the alpha texture, for rays that
+    /// only test for occlusion, such as shadow rays. A hit occludes such rays
+    /// only if it passes both tests.
+    pub fn shadow_alpha(mut self, shadow_alpha: &'a dyn Texture<f32>) -> Self {
+        self.shadow_alpha = Some(shadow_alpha);
+        self
+    }
+
+    /// Builds the triangle mesh, applying the displacement stage, if any, and
+    /// transforming its vertices into world space. The triangles' bounds are
+    /// computed from the displaced vertices, so they enclose the displaced
+    /// surface.
+    ///
+    /// Returns an error if any triangle refers to a vertex that doesn't exist
+    /// or if any per-vertex attribute array doesn't contain exactly one
+    /// element per vertex.
+    pub fn build(self) -> Result<TriangleMesh<'a>, TriangleMeshError> {
+        let vertex_count = self.object_space_vertices.len();
+
+        for (triangle, &(i0, i1, i2)) in self.triangle_vertex_indices.iter().enumerate() {
+            for &vertex_index in [i0, i1, i2].iter() {
+                if vertex_index >= vertex_count {
+                    return Err(TriangleMeshError::VertexIndexOutOfBounds {
+                        triangle,
+                        vertex_index,
+                        vertex_count,
+                    });
+                }
+            }
+        }
+
+        check_attribute_length("tangents", &self.tangents, vertex_count)?;
+        check_attribute_length("normals", &self.normals, vertex_count)?;
+        check_attribute_length("uvs", &self.uvs, vertex_count)?;
+
+        let object_to_world = self.object_to_world;
+        let mesh = DisplacedMesh {
+            vertices: self.object_space_vertices,
+            triangle_vertex_indices: self.triangle_vertex_indices,
+            normals: self.normals,
+            tangents: self.tangents,
+            uvs: self.uvs,
+        };
+        let mesh = match &self.displacement {
+            Some(displacement) => displacement.apply(object_to_world, mesh),
+            None => mesh,
+        };
+        let world_space_vertices: Vec<Point3<f32>> = mesh
+            .vertices
+            .into_iter()
+            .map(|p| object_to_world.transform(&p))
+            .collect();
+        let areas = mesh
+            .triangle_vertex_indices
+            .iter()
+            .map(|&(i0, i1, i2)| {
+                let (p0, p1, p2) = (
+                    world_space_vertices[i0],
+                    world_space_vertices[i1],
+                    world_space_vertices[i2],
+                );
+                0.5 * (p1 - p0).cross(p2 - p0).magnitude()
+            })
+            .collect();
+
+        // Unlike vertices, normals and tangents are interpolated in world
+        // space, so they are transformed up front as well. The normals are
+        // reversed along with the mesh's orientation.
+        let reverse_orientation = self.reverse_orientation;
+        let normals = mesh.normals.map(|normals| {
+            normals
+                .into_iter()
+                .map(|n| {
+                    let n = object_to_world.transform(&vector::Normal3(n)).0;
+                    if reverse_orientation {
+                        -1.0 * n
+                    } else {
+                        n
+                    }
+                })
+                .collect()
+        });
+        let tangents = mesh.tangents.map(|tangents| {
+            tangents
+                .into_iter()
+                .map(|t| object_to_world.transform(&t))
+                .collect()
+        });
+
+        Ok(TriangleMesh {
+            object_to_world,
+            object_to_world_swaps_handedness: object_to_world.swaps_handedness(),
+            reverse_orientation,
+            world_space_vertices,
+            triangle_vertex_indices: mesh.triangle_vertex_indices,
+            tangents,
+            normals,
+            uvs: mesh.uvs,
+            area_distribution: sampling::Distribution1D::new(areas),
+            alpha: self.alpha,
+            shadow_alpha: self.shadow_alpha,
+        })
+    }
 }
 
-impl<'a> Shape<'a> for Triangle<'a> {
-    fn object_to_world(&self) -> &'a Matrix4<f32> {
-        self.mesh.object_to_world
+/// Returns an error if the optional per-vertex attribute array doesn't contain
+/// exactly one element per vertex.
+fn check_attribute_length<T>(
+    attribute: &'static str,
+    values: &Option<Vec<T>>,
+    vertex_count: usize,
+) -> Result<(), TriangleMeshError> {
+    match values {
+        Some(values) if values.len() != vertex_count => {
+            Err(TriangleMeshError::AttributeLengthMismatch {
+                attribute,
+                attribute_count: values.len(),
+                vertex_count,
+            })
+        }
+        _ => Ok(()),
     }
+}
 
-    fn world_to_object(&self) -> &'a Matrix4<f32> {
-        self.mesh.world_to_object
+impl<'a> Shape for Triangle<'a> {
+    fn object_to_world(&self) -> &transform::Transform {
+        self.mesh.object_to_world
     }
 
     fn object_to_world_swaps_handedness(&self) -> bool {
-        todo!();
+        self.mesh.object_to_world_swaps_handedness
     }
 
     fn reverse_orientation(&self) -> bool {
@@ -138,20 +521,405 @@ impl<'a> Shape<'a> for Triangle<'a> {
         test_alpha_texture: bool,
     ) -> Option<(f32, crate::interaction::SurfaceInteraction)> {
         let (p0, p1, p2) = self.world_space_vertices();
-        // TODO: Perform ray-triangle intersection test.
-        // TODO: Compute triangle partial derivatives.
-        // TODO: Compute error bounds for triangle intersection.
-        // TODO: Interpolate (u, v) parametric coordinates and hit point.
-        // TODO: Test interesection against alpha texture, if present.
-        // TODO: Fill in SurfaceInteraction from triangle hit.
-        todo!()
+
+        // Perform ray-triangle intersection test. The triangle is transformed
+        // into a coordinate system where the ray's origin is at the origin and
+        // the ray's direction is along the +z axis so that the test is
+        // watertight along shared edges. (p. 158)
+
+        // Translate vertices based on ray origin.
+        let p0t = p0 - ray.origin;
+        let p1t = p1 - ray.origin;
+        let p2t = p2 - ray.origin;
+
+        // Permute components of triangle vertices and ray direction.
+        let kz = vector::max_dimension(ray.direction.map(|f| f.abs()));
+        let kx = if kz + 1 == 3 { 0 } else { kz + 1 };
+        let ky = if kx + 1 == 3 { 0 } else { kx + 1 };
+        let d = vector::permute(ray.direction, kx, ky, kz);
+        let mut p0t = vector::permute(p0t, kx, ky, kz);
+        let mut p1t = vector::permute(p1t, kx, ky, kz);
+        let mut p2t = vector::permute(p2t, kx, ky, kz);
+
+        // Apply shear transformation to translated vertex positions. Only the
+        // x and y dimensions are sheared now. The z dimension is sheared
+        // later, only if the ray actually intersects the triangle.
+        let shear_x = -1.0 * d.x / d.z;
+        let shear_y = -1.0 * d.y / d.z;
+        let shear_z = 1.0 / d.z;
+        p0t.x += shear_x * p0t.z;
+        p0t.y += shear_y * p0t.z;
+        p1t.x += shear_x * p1t.z;
+        p1t.y += shear_y * p1t.z;
+        p2t.x += shear_x * p2t.z;
+        p2t.y += shear_y * p2t.z;
+
+        // Compute edge function coefficients.
+        let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+        let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+        let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+        // Fall back to double precision test at triangle edges.
+        if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+            e0 = (p1t.x as f64 * p2t.y as f64 - p1t.y as f64 * p2t.x as f64) as f32;
+            e1 = (p2t.x as f64 * p0t.y as f64 - p2t.y as f64 * p0t.x as f64) as f32;
+            e2 = (p0t.x as f64 * p1t.y as f64 - p0t.y as f64 * p1t.x as f64) as f32;
+        }
+
+        // Perform triangle edge and determinant tests.
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
+            return None;
+        }
+
+        // Compute scaled hit distance to triangle and test against ray t
+        // range.
+        p0t.z *= shear_z;
+        p1t.z *= shear_z;
+        p2t.z *= shear_z;
+        let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max * det) {
+            return None;
+        }
+        if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max * det) {
+            return None;
+        }
+
+        // Compute barycentric coordinates and t value for triangle
+        // intersection.
+        let inv_det = 1.0 / det;
+        let b0 = e0 * inv_det;
+        let b1 = e1 * inv_det;
+        let b2 = e2 * inv_det;
+        let t = t_scaled * inv_det;
+
+        // Ensure that the computed triangle t is conservatively greater than
+        // zero. (p. 234)
+        let max_zt = Vector3::new(p0t.z, p1t.z, p2t.z).map(|f| f.abs());
+        let max_zt = max_zt.x.max(max_zt.y).max(max_zt.z);
+        let delta_z = math::gamma(3) * max_zt;
+        let max_xt = p0t.x.abs().max(p1t.x.abs()).max(p2t.x.abs());
+        let max_yt = p0t.y.abs().max(p1t.y.abs()).max(p2t.y.abs());
+        let delta_x = math::gamma(5) * (max_xt + max_zt);
+        let delta_y = math::gamma(5) * (max_yt + max_zt);
+        let delta_e =
+            2.0 * (math::gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t = 3.0
+            * (math::gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e)
+            * inv_det.abs();
+        if t <= delta_t {
+            return None;
+        }
+
+        // Compute triangle partial derivatives.
+        let (uv0, uv1, uv2) = self.uvs();
+        let duv02 = uv0 - uv2;
+        let duv12 = uv1 - uv2;
+        let dp02 = p0 - p2;
+        let dp12 = p1 - p2;
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        let degenerate_uv = determinant.abs() < 1e-8;
+        let (dpdu, dpdv) = if degenerate_uv {
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0))
+        } else {
+            let inv_det = 1.0 / determinant;
+            (
+                (duv12.y * dp02 - duv02.y * dp12) * inv_det,
+                (duv02.x * dp12 - duv12.x * dp02) * inv_det,
+            )
+        };
+        let (dpdu, dpdv) = if degenerate_uv || dpdu.cross(dpdv).magnitude2() == 0.0 {
+            // Handle the zero determinant case for triangle partial derivatives
+            // by choosing an arbitrary coordinate system about the triangle's
+            // geometric normal.
+            let ng = (p2 - p0).cross(p1 - p0);
+            if ng.magnitude2() == 0.0 {
+                // The triangle is degenerate.
+                return None;
+            }
+            vector::coordinate_system(ng.normalize())
+        } else {
+            (dpdu, dpdv)
+        };
+
+        // Compute error bounds for triangle intersection. (p. 227)
+        let x_abs_sum = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
+        let y_abs_sum = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
+        let z_abs_sum = (b0 * p0.z).abs() + (b1 * p1.z).abs() + (b2 * p2.z).abs();
+        let p_error = math::gamma(7) * Vector3::new(x_abs_sum, y_abs_sum, z_abs_sum);
+
+        // Interpolate (u, v) parametric coordinates and hit point.
+        let p_hit = Point3::from_vec(b0 * p0.to_vec() + b1 * p1.to_vec() + b2 * p2.to_vec());
+        let uv_hit = Point2::from_vec(b0 * uv0.to_vec() + b1 * uv1.to_vec() + b2 * uv2.to_vec());
+
+        // Fill in SurfaceInteraction from triangle hit.
+        let mut interaction = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            Some(-1.0 * ray.direction),
+            ray.time,
+            self,
+            uv_hit,
+            dpdu,
+            dpdv,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+
+        // Override the surface normal with the true geometric normal of the
+        // triangle. (p. 165)
+        let geometric_normal = dp02.cross(dp12).normalize();
+        let geometric_normal =
+            if self.reverse_orientation() ^ self.object_to_world_swaps_handedness() {
+                -1.0 * geometric_normal
+            } else {
+                geometric_normal
+            };
+        interaction.set_normal(geometric_normal);
+
+        // Test intersection against alpha texture, if present.
+        if let (true, Some(alpha)) = (test_alpha_texture, self.mesh.alpha) {
+            if !passes_alpha_test(alpha, &interaction, ray, (self.index_in_mesh, "alpha")) {
+                return None;
+            }
+        }
+
+        // Initialize triangle shading geometry if the mesh has per-vertex
+        // normals or tangents.
+        if self.mesh.normals.is_some() || self.mesh.tangents.is_some() {
+            let (ss, ts, dndu, dndv) =
+                self.shading_geometry((b0, b1, b2), duv02, duv12, geometric_normal, dpdu);
+            // The interpolated normals are authoritative when present, so the
+            // geometric normal is flipped to lie in the same hemisphere.
+            interaction.set_shading_geometry(ss, ts, dndu, dndv, self.mesh.normals.is_some());
+        }
+
+        Some((t, interaction))
     }
 
+    /// Tests the hit against the shadow alpha texture on top of the alpha
+    /// texture. A ray can only hit a triangle once, so testing the hit found
+    /// with the alpha texture is enough. The two tests make independent
+    /// choices, so a hit occludes with the product of the two alphas.
     fn does_ray_intersect(&self, ray: &crate::ray::Ray, test_alpha_texture: bool) -> bool {
-        self.ray_intersection(ray, test_alpha_texture).is_some()
+        match self.ray_intersection(ray, test_alpha_texture) {
+            Some((_, interaction)) => match (test_alpha_texture, self.mesh.shadow_alpha) {
+                (true, Some(shadow_alpha)) => passes_alpha_test(
+                    shadow_alpha,
+                    &interaction,
+                    ray,
+                    (self.index_in_mesh, "shadow alpha"),
+                ),
+                _ => true,
+            },
+            None => false,
+        }
     }
 
     fn surface_area(&self) -> f32 {
-        todo!()
+        let (p0, p1, p2) = self.world_space_vertices();
+        0.5 * (p1 - p0).cross(p2 - p0).magnitude()
+    }
+
+    /// Samples the triangle uniformly by area. (p. 839)
+    fn sample_area(&self, u: Point2<f32>) -> Option<(SurfacePoint, f32)> {
+        let area = self.surface_area();
+        if area == 0.0 {
+            return None;
+        }
+        let (b0, b1) = sampling::uniform_sample_triangle(u);
+        Some((self.sampled_point((b0, b1, 1.0 - b0 - b1)), 1.0 / area))
+    }
+
+    /// Samples the solid angle that the triangle subtends from the reference
+    /// point uniformly. Triangles that subtend a very small or very large
+    /// solid angle are sampled by area instead, since spherical triangle
+    /// sampling is inaccurate for them.
+    fn sample_solid_angle(
+        &self,
+        reference: &dyn Interaction,
+        u: Point2<f32>,
+    ) -> Option<(SurfacePoint, f32)> {
+        let solid_angle = self.solid_angle(reference.point());
+        if !(MIN_SPHERICAL_SAMPLE_AREA..=MAX_SPHERICAL_SAMPLE_AREA).contains(&solid_angle) {
+            return sample_solid_angle_by_area(self, reference, u);
+        }
+
+        let (p0, p1, p2) = self.world_space_vertices();
+        let (b, pdf) = sampling::sample_spherical_triangle([p0, p1, p2], reference.point(), u)?;
+        let mut point = self.sampled_point((b[0], b[1], b[2]));
+        point.set_time(reference.time());
+        Some((point, pdf))
+    }
+
+    fn pdf_solid_angle(&self, reference: &dyn Interaction, wi: Vector3<f32>) -> f32 {
+        let solid_angle = self.solid_angle(reference.point());
+        if !(MIN_SPHERICAL_SAMPLE_AREA..=MAX_SPHERICAL_SAMPLE_AREA).contains(&solid_angle) {
+            return pdf_solid_angle_by_area(self, reference, wi);
+        }
+
+        // Directions are sampled uniformly over the spherical triangle, so the
+        // density is constant for directions that hit the triangle.
+        if self
+            .ray_intersection(&reference.spawn_ray(wi), false)
+            .is_some()
+        {
+            1.0 / solid_angle
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::assert_area_sampling;
+    use super::super::test_util::assert_solid_angle_sampling;
+    use super::TiangleMeshBuilder;
+    use super::TriangleMesh;
+    use super::MAX_SPHERICAL_SAMPLE_AREA;
+    use super::MIN_SPHERICAL_SAMPLE_AREA;
+    use crate::ray::Ray;
+    use crate::sampling;
+    use crate::shape::Shape;
+    use crate::texture::ConstantTexture;
+    use crate::transform::Transform;
+    use cgmath::InnerSpace;
+    use cgmath::Point3;
+    use cgmath::Vector3;
+
+    /// Returns the solid angle that the triangle with vertices `p0`, `p1` and
+    /// `p2` subtends from `p`.
+    fn solid_angle(p0: Point3<f32>, p1: Point3<f32>, p2: Point3<f32>, p: Point3<f32>) -> f32 {
+        sampling::spherical_triangle_area(
+            (p0 - p).normalize(),
+            (p1 - p).normalize(),
+            (p2 - p).normalize(),
+        )
+    }
+
+    /// Returns the fraction of a grid of rays straight down through the
+    /// triangles of `mesh` that are occluded by at least one of them.
+    fn occluded_fraction(mesh: &TriangleMesh) -> f32 {
+        const N: usize = 64;
+        let mut occluded = 0;
+        for i in 0..N {
+            for j in 0..N {
+                let ray = Ray {
+                    origin: Point3::new(
+                        0.1 + 0.8 * (i as f32 + 0.5) / N as f32,
+                        0.1 + 0.8 * (j as f32 + 0.5) / N as f32,
+                        3.0,
+                    ),
+                    direction: Vector3::new(0.0, 0.0, -1.0),
+                    t_max: f32::INFINITY,
+                    ..Default::default()
+                };
+                if mesh.triangles().any(|t| t.does_ray_intersect(&ray, true)) {
+                    occluded += 1;
+                }
+            }
+        }
+        occluded as f32 / (N * N) as f32
+    }
+
+    /// Returns a builder for a mesh of right triangles with legs of length 2
+    /// along x and y at each of the heights `zs`.
+    fn layers<'a>(object_to_world: &'a Transform, zs: &[f32]) -> TiangleMeshBuilder<'a> {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        for &z in zs {
+            let i = vertices.len();
+            vertices.push(Point3::new(0.0, 0.0, z));
+            vertices.push(Point3::new(2.0, 0.0, z));
+            vertices.push(Point3::new(0.0, 2.0, z));
+            indices.push((i, i + 1, i + 2));
+        }
+        TiangleMeshBuilder::new(object_to_world, false, vertices, indices)
+    }
+
+    #[test]
+    fn alpha_and_shadow_alpha_occlude_independently() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let half = ConstantTexture::new(0.5);
+        let mesh = layers(&object_to_world, &[0.0])
+            .alpha(&half)
+            .shadow_alpha(&half)
+            .build()
+            .unwrap();
+        let fraction = occluded_fraction(&mesh);
+        assert!((fraction - 0.25).abs() < 0.03, "fraction {}", fraction);
+    }
+
+    #[test]
+    fn stacked_alpha_layers_occlude_independently() {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let half = ConstantTexture::new(0.5);
+        let mesh = layers(&object_to_world, &[0.0, 1.0])
+            .alpha(&half)
+            .build()
+            .unwrap();
+        let fraction = occluded_fraction(&mesh);
+        assert!((fraction - 0.75).abs() < 0.03, "fraction {}", fraction);
+    }
+
+    #[test]
+    fn sample_area_is_uniform() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let vertices = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.5),
+            Point3::new(0.0, 1.5, 0.0),
+        ];
+        let mesh = TiangleMeshBuilder::new(&object_to_world, false, vertices, vec![(0, 1, 2)])
+            .build()
+            .unwrap();
+        let triangle = mesh.triangle_at(0);
+        let (p0, p1, p2) = triangle.world_space_vertices();
+        assert_area_sampling(&triangle, 0.5 * (p1 - p0).cross(p2 - p0).magnitude());
+    }
+
+    #[test]
+    fn sample_solid_angle_samples_spherical_triangle() {
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let vertices = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.5),
+            Point3::new(0.0, 1.5, 0.0),
+        ];
+        let mesh = TiangleMeshBuilder::new(&object_to_world, false, vertices, vec![(0, 1, 2)])
+            .build()
+            .unwrap();
+        let triangle = mesh.triangle_at(0);
+        let (p0, p1, p2) = triangle.world_space_vertices();
+        let reference = Point3::new(0.5, 0.7, 4.0);
+        let expected = solid_angle(p0, p1, p2, reference);
+        assert!((MIN_SPHERICAL_SAMPLE_AREA..=MAX_SPHERICAL_SAMPLE_AREA).contains(&expected));
+        assert_solid_angle_sampling(&triangle, reference, expected);
+    }
+
+    #[test]
+    fn sample_solid_angle_falls_back_to_area() {
+        // The triangle subtends too small a solid angle from far away to be
+        // sampled as a spherical triangle.
+        let object_to_world = Transform::translate(Vector3::new(0.3, -0.2, 0.1));
+        let vertices = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.5),
+            Point3::new(0.0, 1.5, 0.0),
+        ];
+        let mesh = TiangleMeshBuilder::new(&object_to_world, false, vertices, vec![(0, 1, 2)])
+            .build()
+            .unwrap();
+        let triangle = mesh.triangle_at(0);
+        let (p0, p1, p2) = triangle.world_space_vertices();
+        let reference = Point3::new(0.5, 0.7, 200.0);
+        let expected = solid_angle(p0, p1, p2, reference);
+        assert!(expected < MIN_SPHERICAL_SAMPLE_AREA);
+        assert_solid_angle_sampling(&triangle, reference, expected);
     }
 }
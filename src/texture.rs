@@ -0,0 +1,64 @@
+use crate::interaction::Interaction;
+use crate::interaction::SurfaceInteraction;
+
+/// The geometry at a point on a surface that a texture is evaluated at.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureEvalContext {
+    /// The point in world space.
+    pub point: cgmath::Point3<f32>,
+
+    /// The surface normal in world space.
+    pub normal: cgmath::Vector3<f32>,
+
+    /// The UV coordinates of the point on the surface.
+    pub uv: cgmath::Point2<f32>,
+}
+
+impl TextureEvalContext {
+    /// Returns the context for evaluating a texture at a ray's intersection
+    /// with a surface, using the surface's geometric normal.
+    pub fn from_interaction(interaction: &SurfaceInteraction) -> Self {
+        Self {
+            point: interaction.point(),
+            normal: interaction
+                .normal()
+                .unwrap_or_else(|| cgmath::Vector3::new(0.0, 0.0, 0.0)),
+            uv: interaction.uv(),
+        }
+    }
+}
+
+/// A function that varies over a surface, such as a displacement or an alpha
+/// value. (p. 614)
+pub trait Texture<T> {
+    /// Returns the texture's value at the given point on a surface.
+    fn evaluate(&self, context: &TextureEvalContext) -> T;
+}
+
+/// A texture that has the same value everywhere. (p. 615)
+pub struct ConstantTexture<T> {
+    value: T,
+}
+
+impl<T> ConstantTexture<T> {
+    pub fn new(value: T) -> Self {
+        Self { value }
+    }
+}
+
+impl<T: Copy> Texture<T> for ConstantTexture<T> {
+    fn evaluate(&self, _context: &TextureEvalContext) -> T {
+        self.value
+    }
+}
+
+/// Any function of the surface geometry can be used as a texture, which allows
+/// procedural textures to be written as closures.
+impl<T, F> Texture<T> for F
+where
+    F: Fn(&TextureEvalContext) -> T,
+{
+    fn evaluate(&self, context: &TextureEvalContext) -> T {
+        self(context)
+    }
+}
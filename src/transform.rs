@@ -1,11 +1,38 @@
+use crate::math;
+use crate::vector::Normal3;
+use cgmath::InnerSpace;
+use cgmath::Matrix;
 use cgmath::SquareMatrix;
+use cgmath::Transform as CgmathTransform;
 
 /// A trait representing an affine transformation that can be applied to data
 /// structures containing points or vectors.
-pub trait Transform<T> {
+pub trait ApplyTransform<T> {
     fn transform(&self, t: &T) -> T;
 }
 
+/// A trait representing an affine transformation that can be applied to data
+/// structures containing points or vectors while tracking a conservative bound
+/// on the absolute floating-point error introduced by the transformation.
+/// (p. 228)
+pub trait ApplyTransformWithError<T> {
+    /// A conservative bound on the absolute error in each dimension of the
+    /// transformed data.
+    type ErrorBound;
+
+    /// Transforms `t` and returns the result along with a bound on the
+    /// round-off error introduced by the transformation.
+    fn transform_with_error(&self, t: &T) -> (T, Self::ErrorBound);
+
+    /// Transforms `t`, which already carries the error bound `t_error`, and
+    /// returns the result along with a bound on the total error in the result.
+    fn transform_with_error_bound(
+        &self,
+        t: &T,
+        t_error: &Self::ErrorBound,
+    ) -> (T, Self::ErrorBound);
+}
+
 /// A trait that allows an affine transformation to indicate whether it swaps
 /// the coordinate system handedness.
 pub trait SwapHandedness {
@@ -23,3 +50,274 @@ impl SwapHandedness for cgmath::Matrix4<f32> {
         m3.determinant() < 0.0
     }
 }
+
+/// A transformation that stores its matrix together with the matrix's inverse
+/// so that the two can never become inconsistent and so that the inverse
+/// never needs to be recomputed. (p. 83)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    matrix: cgmath::Matrix4<f32>,
+    inverse: cgmath::Matrix4<f32>,
+}
+
+impl Transform {
+    /// Creates a transformation from the given matrix. Returns `None` if the
+    /// matrix is not invertible.
+    pub fn new(matrix: cgmath::Matrix4<f32>) -> Option<Self> {
+        matrix.invert().map(|inverse| Self { matrix, inverse })
+    }
+
+    /// Creates a transformation from the given matrix and its inverse. The
+    /// caller is responsible for ensuring that `inverse` is the inverse of
+    /// `matrix`.
+    pub fn from_matrices(matrix: cgmath::Matrix4<f32>, inverse: cgmath::Matrix4<f32>) -> Self {
+        Self { matrix, inverse }
+    }
+
+    /// Creates the identity transformation.
+    pub fn identity() -> Self {
+        Self {
+            matrix: cgmath::Matrix4::identity(),
+            inverse: cgmath::Matrix4::identity(),
+        }
+    }
+
+    /// Creates a transformation that translates points by `delta`. (p. 87)
+    pub fn translate(delta: cgmath::Vector3<f32>) -> Self {
+        Self {
+            matrix: cgmath::Matrix4::from_translation(delta),
+            inverse: cgmath::Matrix4::from_translation(-1.0 * delta),
+        }
+    }
+
+    /// Creates a transformation that scales points and vectors by the given
+    /// factor in each dimension. (p. 89)
+    pub fn scale(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            matrix: cgmath::Matrix4::from_nonuniform_scale(x, y, z),
+            inverse: cgmath::Matrix4::from_nonuniform_scale(1.0 / x, 1.0 / y, 1.0 / z),
+        }
+    }
+
+    /// Creates a transformation that rotates `theta` degrees about the x axis.
+    /// (p. 89)
+    pub fn rotate_x(theta: f32) -> Self {
+        Self::from_rotation(cgmath::Matrix4::from_angle_x(cgmath::Deg(theta)))
+    }
+
+    /// Creates a transformation that rotates `theta` degrees about the y axis.
+    pub fn rotate_y(theta: f32) -> Self {
+        Self::from_rotation(cgmath::Matrix4::from_angle_y(cgmath::Deg(theta)))
+    }
+
+    /// Creates a transformation that rotates `theta` degrees about the z axis.
+    pub fn rotate_z(theta: f32) -> Self {
+        Self::from_rotation(cgmath::Matrix4::from_angle_z(cgmath::Deg(theta)))
+    }
+
+    /// Creates a transformation that rotates `theta` degrees about an
+    /// arbitrary axis. (p. 91)
+    pub fn rotate(theta: f32, axis: cgmath::Vector3<f32>) -> Self {
+        Self::from_rotation(cgmath::Matrix4::from_axis_angle(
+            axis.normalize(),
+            cgmath::Deg(theta),
+        ))
+    }
+
+    /// Creates a transformation from a rotation matrix. The inverse of a
+    /// rotation matrix is its transpose.
+    fn from_rotation(matrix: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    /// Creates a transformation from world space to the space of a camera
+    /// positioned at `position` and looking at `look`, with the camera's up
+    /// direction oriented towards `up`. Returns `None` if `up` and the viewing
+    /// direction are parallel. (p. 92)
+    pub fn look_at(
+        position: cgmath::Point3<f32>,
+        look: cgmath::Point3<f32>,
+        up: cgmath::Vector3<f32>,
+    ) -> Option<Self> {
+        let dir = (look - position).normalize();
+        let right = up.normalize().cross(dir);
+        if right.magnitude2() == 0.0 {
+            return None;
+        }
+        let right = right.normalize();
+        let new_up = dir.cross(right);
+
+        let camera_to_world = cgmath::Matrix4::from_cols(
+            right.extend(0.0),
+            new_up.extend(0.0),
+            dir.extend(0.0),
+            cgmath::Vector4::new(position.x, position.y, position.z, 1.0),
+        );
+        camera_to_world
+            .invert()
+            .map(|world_to_camera| Self::from_matrices(world_to_camera, camera_to_world))
+    }
+
+    /// Creates a perspective projection with a field of view of `fov` degrees
+    /// that maps points between the `z_near` and `z_far` planes to z values
+    /// between 0 and 1. (p. 365)
+    pub fn perspective(fov: f32, z_near: f32, z_far: f32) -> Option<Self> {
+        let persp = cgmath::Matrix4::from_cols(
+            cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+            cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+            cgmath::Vector4::new(0.0, 0.0, z_far / (z_far - z_near), 1.0),
+            cgmath::Vector4::new(0.0, 0.0, -1.0 * z_far * z_near / (z_far - z_near), 0.0),
+        );
+        let inv_tan_ang = 1.0 / (fov.to_radians() / 2.0).tan();
+        Self::new(persp).map(|persp| Self::scale(inv_tan_ang, inv_tan_ang, 1.0) * persp)
+    }
+
+    /// Creates an orthographic projection that maps points between the
+    /// `z_near` and `z_far` planes to z values between 0 and 1. (p. 359)
+    pub fn orthographic(z_near: f32, z_far: f32) -> Self {
+        Self::scale(1.0, 1.0, 1.0 / (z_far - z_near))
+            * Self::translate(cgmath::Vector3::new(0.0, 0.0, -1.0 * z_near))
+    }
+
+    /// Returns the transformation's matrix.
+    pub fn matrix(&self) -> &cgmath::Matrix4<f32> {
+        &self.matrix
+    }
+
+    /// Returns the inverse of the transformation's matrix.
+    pub fn inverse_matrix(&self) -> &cgmath::Matrix4<f32> {
+        &self.inverse
+    }
+
+    /// Returns the inverse transformation. This doesn't require any
+    /// computation since the inverse matrix is already stored.
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    /// Returns the transposed transformation.
+    pub fn transpose(&self) -> Self {
+        Self {
+            matrix: self.matrix.transpose(),
+            inverse: self.inverse.transpose(),
+        }
+    }
+
+    /// Returns true if and only if the transformation is the identity.
+    pub fn is_identity(&self) -> bool {
+        self.matrix == cgmath::Matrix4::identity()
+    }
+}
+
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+
+    /// Returns the composition of the two transformations. Applying the result
+    /// is equivalent to applying `rhs` followed by `self`.
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+        }
+    }
+}
+
+impl SwapHandedness for Transform {
+    fn swaps_handedness(&self) -> bool {
+        self.matrix.swaps_handedness()
+    }
+}
+
+impl ApplyTransform<cgmath::Point3<f32>> for Transform {
+    fn transform(&self, p: &cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+        self.matrix.transform_point(*p)
+    }
+}
+
+impl ApplyTransform<cgmath::Vector3<f32>> for Transform {
+    fn transform(&self, v: &cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        self.matrix.transform_vector(*v)
+    }
+}
+
+impl ApplyTransform<Normal3<f32>> for Transform {
+    /// Transforms the normal by the inverse transpose of the transformation's
+    /// matrix so that it remains perpendicular to the surface. (p. 93)
+    fn transform(&self, n: &Normal3<f32>) -> Normal3<f32> {
+        let inv_transpose = self.inverse.transpose();
+        Normal3(inv_transpose.transform_vector(n.0))
+    }
+}
+
+impl ApplyTransformWithError<cgmath::Point3<f32>> for Transform {
+    type ErrorBound = cgmath::Vector3<f32>;
+
+    fn transform_with_error(
+        &self,
+        p: &cgmath::Point3<f32>,
+    ) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        // Compute an absolute error bound for the transformed point. (p. 228)
+        let m = &self.matrix;
+        let err = cgmath::Vector3::new(
+            (m[0][0] * p.x).abs() + (m[1][0] * p.y).abs() + (m[2][0] * p.z).abs() + m[3][0].abs(),
+            (m[0][1] * p.x).abs() + (m[1][1] * p.y).abs() + (m[2][1] * p.z).abs() + m[3][1].abs(),
+            (m[0][2] * p.x).abs() + (m[1][2] * p.y).abs() + (m[2][2] * p.z).abs() + m[3][2].abs(),
+        );
+        (self.transform(p), math::gamma(3) * err)
+    }
+
+    fn transform_with_error_bound(
+        &self,
+        p: &cgmath::Point3<f32>,
+        p_error: &cgmath::Vector3<f32>,
+    ) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        // Compute an absolute error bound for the transformed point that
+        // accounts for the point's existing error. (p. 229)
+        let m = &self.matrix;
+        let (transformed, err) = self.transform_with_error(p);
+        let carried_err = cgmath::Vector3::new(
+            m[0][0].abs() * p_error.x + m[1][0].abs() * p_error.y + m[2][0].abs() * p_error.z,
+            m[0][1].abs() * p_error.x + m[1][1].abs() * p_error.y + m[2][1].abs() * p_error.z,
+            m[0][2].abs() * p_error.x + m[1][2].abs() * p_error.y + m[2][2].abs() * p_error.z,
+        );
+        (transformed, (math::gamma(3) + 1.0) * carried_err + err)
+    }
+}
+
+impl ApplyTransformWithError<cgmath::Vector3<f32>> for Transform {
+    type ErrorBound = cgmath::Vector3<f32>;
+
+    fn transform_with_error(
+        &self,
+        v: &cgmath::Vector3<f32>,
+    ) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let m = &self.matrix;
+        let err = cgmath::Vector3::new(
+            (m[0][0] * v.x).abs() + (m[1][0] * v.y).abs() + (m[2][0] * v.z).abs(),
+            (m[0][1] * v.x).abs() + (m[1][1] * v.y).abs() + (m[2][1] * v.z).abs(),
+            (m[0][2] * v.x).abs() + (m[1][2] * v.y).abs() + (m[2][2] * v.z).abs(),
+        );
+        (self.transform(v), math::gamma(3) * err)
+    }
+
+    fn transform_with_error_bound(
+        &self,
+        v: &cgmath::Vector3<f32>,
+        v_error: &cgmath::Vector3<f32>,
+    ) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let m = &self.matrix;
+        let (transformed, err) = self.transform_with_error(v);
+        let carried_err = cgmath::Vector3::new(
+            m[0][0].abs() * v_error.x + m[1][0].abs() * v_error.y + m[2][0].abs() * v_error.z,
+            m[0][1].abs() * v_error.x + m[1][1].abs() * v_error.y + m[2][1].abs() * v_error.z,
+            m[0][2].abs() * v_error.x + m[1][2].abs() * v_error.y + m[2][2].abs() * v_error.z,
+        );
+        (transformed, (math::gamma(3) + 1.0) * carried_err + err)
+    }
+}
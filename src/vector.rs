@@ -1,5 +1,10 @@
 use cgmath::InnerSpace;
 
+/// A surface normal. Normals are represented by a distinct type from vectors
+/// because they are transformed differently. (p. 71)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normal3<S>(pub cgmath::Vector3<S>);
+
 /// If the angle between `v1` and `v2` is less than 90 degrees then return `v1`.
 /// Otherwise flip and return `v1` so that it is in the same hemisphere as `v2`.
 pub fn face_forward<S: cgmath::BaseNum>(
@@ -12,3 +17,42 @@ pub fn face_forward<S: cgmath::BaseNum>(
         v1
     }
 }
+
+/// Returns two vectors that, along with `v1`, form an orthogonal coordinate
+/// system. `v1` is assumed to be normalized. (p. 67)
+pub fn coordinate_system(v1: cgmath::Vector3<f32>) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+    let v2 = if v1.x.abs() > v1.y.abs() {
+        cgmath::Vector3::new(-1.0 * v1.z, 0.0, v1.x) / (v1.x * v1.x + v1.z * v1.z).sqrt()
+    } else {
+        cgmath::Vector3::new(0.0, v1.z, -1.0 * v1.y) / (v1.y * v1.y + v1.z * v1.z).sqrt()
+    };
+    let v3 = v1.cross(v2);
+    (v2, v3)
+}
+
+/// Returns the index of the dimension with the largest value.
+pub fn max_dimension<S: cgmath::BaseNum>(v: cgmath::Vector3<S>) -> usize {
+    if v.x > v.y {
+        if v.x > v.z {
+            0
+        } else {
+            2
+        }
+    } else if v.y > v.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// Returns a new vector with the dimensions of `v` rearranged so that the
+/// `x`, `y`, and `z` dimensions of the new vector are the `i`, `j`, and `k`
+/// dimensions of `v`, respectively.
+pub fn permute<S: cgmath::BaseNum>(
+    v: cgmath::Vector3<S>,
+    i: usize,
+    j: usize,
+    k: usize,
+) -> cgmath::Vector3<S> {
+    cgmath::Vector3::new(v[i], v[j], v[k])
+}